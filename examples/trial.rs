@@ -1,4 +1,5 @@
 use anyhow::Result;
+use std::process::ExitCode;
 
 use hiercmd::prelude::*;
 
@@ -21,20 +22,28 @@ async fn do_thing_list(mut l: Level<()>) -> Result<()> {
     r.add_u64("number", 2);
     t.add_row(r);
 
-    print!("{}", t.output()?);
+    write!(l.stdout(), "{}", t.output()?)?;
     Ok(())
 }
 
 async fn do_thing(mut l: Level<()>) -> Result<()> {
     l.cmd("list", "list things", cmd!(do_thing_list))?;
+    l.default_cmd("list")?;
+    l.prefix_match(true);
     sel!(l).run().await
 }
 
 async fn do_info(mut l: Level<()>) -> Result<()> {
     l.usage_args(Some("[THING...]"));
+    l.optopt("n", "", "show at most this many things", "COUNT");
     let a = args!(l);
-    for (i, arg) in a.args().iter().enumerate() {
-        println!("[{:02}] {}", i, arg);
+    if a.globals().opt_present("x") {
+        writeln!(l.stdout(), "extended information:")?;
+    }
+    let limit = a.opt_parse::<usize>("n")?.unwrap_or(usize::MAX);
+    debug!(l.logger(), "showing information"; "limit" => limit);
+    for (i, arg) in a.args().iter().take(limit).enumerate() {
+        writeln!(l.stdout(), "[{:02}] {}", i, arg)?;
     }
     Ok(())
 }
@@ -45,44 +54,112 @@ async fn do_nothing(mut l: Level<()>) -> Result<()> {
 }
 
 async fn do_check(mut l: Level<()>) -> Result<()> {
-    l.usage_args(Some("WORD"));
+    l.positional("WORD", "the word to check", Arity::Required)?;
     let a = args!(l);
-    if !a.args().len() != 1 {
-        bad_args!(l, "specify exactly one word");
-    }
+    writeln!(l.stdout(), "{} is a word", a.positional("WORD").unwrap())?;
     Ok(())
 }
 
 async fn do_withreq(mut l: Level<()>) -> Result<()> {
     l.reqopt("a", "first", "first letter", "LETTER");
     l.reqopt("", "second", "second letter", "LETTER");
+    l.option_env("second", "TRIAL_SECOND")?;
     l.reqopt("c", "", "third letter", "LETTER");
     l.optopt("x", "", "optional extra letter", "LETTER");
     let a = args!(l);
     for opt in ["a", "second", "c"] {
-        println!("{} = {}", opt, a.opts().opt_str(opt).unwrap());
+        writeln!(l.stdout(), "{} = {}", opt, a.opts().opt_str(opt).unwrap())?;
     }
     for opt in ["x"] {
-        println!("{} = {:?}", opt, a.opts().opt_str(opt));
+        writeln!(l.stdout(), "{} = {:?}", opt, a.opts().opt_str(opt))?;
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let mut l = hiercmd::Level::new("trial", ());
+async fn do_say(mut l: Level<()>, word: &str) -> Result<()> {
+    no_args!(l);
+    writeln!(l.stdout(), "{}!", word)?;
+    Ok(())
+}
+
+async fn do_shell(mut l: Level<()>) -> Result<()> {
+    no_args!(l);
+    hiercmd::repl::run(l, trial).await
+}
+
+async fn do_completion(mut l: Level<()>) -> Result<()> {
+    l.usage_args(Some("bash|zsh|fish"));
+    let a = args!(l);
+    if a.args().len() != 1 {
+        bad_args!(l, "specify a shell");
+    }
+    let shell = match a.args()[0].parse() {
+        Ok(shell) => shell,
+        Err(e) => bad_args!(l, "{}", e),
+    };
+    write!(l.stdout(), "{}", hiercmd::complete::script(shell, "trial"))?;
+    Ok(())
+}
+
+async fn do_manpage(mut l: Level<()>) -> Result<()> {
+    no_args!(l);
+    let page = hiercmd::man::combined("trial", (), trial).await?;
+    write!(l.stdout(), "{}", page)?;
+    Ok(())
+}
+
+async fn do_describe(mut l: Level<()>) -> Result<()> {
+    no_args!(l);
+    let tree = hiercmd::json::tree("trial", (), trial).await?;
+    writeln!(l.stdout(), "{}", serde_json::to_string_pretty(&tree)?)?;
+    Ok(())
+}
+
+/*
+ * Handlers below the top level refer back to it through this function, as its
+ * concrete return type allows the compiler to check that the handler futures
+ * are Send without going around in circles.
+ */
+fn trial(l: Level<()>) -> HandlerFuture {
+    Box::pin(do_trial(l))
+}
+
+async fn do_trial(mut l: Level<()>) -> Result<()> {
     l.cmd("info", "get information", cmd!(do_info))?;
     l.cmda("thing", "th", "manage things", cmd!(do_thing))?;
+    l.halias("thing", "things")?;
     l.cmd("nothing", "do nothing", cmd!(do_nothing))?;
     l.cmd("check", "check to see if a word is valid", cmd!(do_check))?;
     l.cmd("withreq", "try required arguments", cmd!(do_withreq))?;
+    l.cmd("hello", "say hello", |l| do_say(l, "hello"))?;
+    l.cmd("goodbye", "say goodbye", |l| do_say(l, "goodbye"))?;
+    l.cmd("shell", "run commands interactively", cmd!(do_shell))?;
+    l.hcmd(
+        "completion",
+        "generate completion script",
+        cmd!(do_completion),
+    )?;
+    l.hcmd("manpage", "generate manual page", cmd!(do_manpage))?;
+    l.hcmd("describe", "describe commands as JSON", cmd!(do_describe))?;
+    l.prefix_match(true);
+    l.plugin_commands(true);
+    if let Some(home) = std::env::var_os("HOME") {
+        l.config_file(&std::path::Path::new(&home).join(".trial.toml"));
+    }
 
-    l.optflag("x", "", "extend");
+    l.global_optflag("x", "", "extend");
+    l.standard_logging();
+    l.debug_errors();
 
     let s = sel!(l);
     if s.opts().opt_present("x") {
-        println!("eXtended!");
+        writeln!(s.stdout(), "eXtended!")?;
     }
 
     s.run().await
 }
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    hiercmd::run(Level::new("trial", ()), cmd!(do_trial)).await
+}
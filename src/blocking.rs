@@ -0,0 +1,94 @@
+/*!
+ * Work that blocks the calling thread, such as waiting for an external command
+ * to exit or for the user to enter a line, must not be done on a thread of the
+ * async executor that runs the handlers, or other tasks would be held up until
+ * it finished.  This module runs such work on a thread of its own, without
+ * depending on any particular executor.
+ */
+
+use std::future::Future;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::Result as ThreadResult;
+
+struct State<T> {
+    result: Option<ThreadResult<T>>,
+    waker: Option<Waker>,
+}
+
+/**
+ * A future that completes with the result of the work passed to `unblock()`.
+ * If the work panicked, the panic is resumed when the future is polled.
+ */
+pub(crate) struct Unblock<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/**
+ * Start running `func` on a new thread, returning a future that completes
+ * with its result.
+ */
+pub(crate) fn unblock<T, F>(func: F) -> Unblock<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let state = Arc::new(Mutex::new(State {
+        result: None,
+        waker: None,
+    }));
+
+    let inner = Arc::clone(&state);
+    std::thread::spawn(move || {
+        let result = catch_unwind(AssertUnwindSafe(func));
+        let mut state = inner.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+
+    Unblock { state }
+}
+
+impl<T> Future for Unblock<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unblock;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn unblocked() {
+        /*
+         * The test runtime has only one thread, so if the work were done on
+         * it, the task that allows the work to finish could never run.
+         */
+        let (tx, rx) = mpsc::channel();
+        let other = tokio::spawn(async move { tx.send(3).unwrap() });
+        assert_eq!(unblock(move || rx.recv().unwrap()).await, 3);
+        other.await.unwrap();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "it broke")]
+    async fn unblocked_panic() {
+        unblock(|| panic!("it broke")).await
+    }
+}
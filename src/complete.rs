@@ -0,0 +1,294 @@
+/*!
+ * Shell completion support.  The generated completion scripts call back into
+ * the program with the hidden `__complete` command, passing the words on the
+ * command line so far and the (possibly empty) word being completed.  The
+ * level handlers are then run far enough to discover the commands, options,
+ * and columns for the level being completed, and the candidates are printed
+ * one per line.
+ */
+
+use super::describe::{Describer, LevelInfo};
+use super::{Caller, Level, LevelContext, OptionInfo, Output, HELP_COMMAND};
+use anyhow::{bail, Result};
+use std::io::Write;
+
+pub(crate) const COMPLETE_COMMAND: &str = "__complete";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Shell> {
+        Ok(match s {
+            "bash" => Shell::Bash,
+            "zsh" => Shell::Zsh,
+            "fish" => Shell::Fish,
+            other => bail!("unknown shell \"{}\"", other),
+        })
+    }
+}
+
+/**
+ * Generate a completion script for the program `name` in the syntax of the
+ * nominated shell.  The program should arrange to emit this script somehow;
+ * e.g., from a hidden "completion" sub-command, so that users can load it with
+ * something like `source <(trial completion bash)`.
+ */
+pub fn script(shell: Shell, name: &str) -> String {
+    let func = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    match shell {
+        Shell::Bash => format!(
+            "_{func}() {{\n\
+            \x20   local IFS=$'\\n'\n\
+            \x20   COMPREPLY=($({name} {cc} \
+            \"${{COMP_WORDS[@]:1:COMP_CWORD}}\" 2>/dev/null))\n\
+            }}\n\
+            complete -o default -F _{func} {name}\n",
+            func = func,
+            name = name,
+            cc = COMPLETE_COMMAND,
+        ),
+        Shell::Zsh => format!(
+            "#compdef {name}\n\
+            _{func}() {{\n\
+            \x20   local -a candidates\n\
+            \x20   candidates=(${{(f)\"$({name} {cc} \
+            \"${{(@)words[2,CURRENT]}}\" 2>/dev/null)\"}})\n\
+            \x20   if (( ${{#candidates}} == 0 )); then\n\
+            \x20       _files\n\
+            \x20   else\n\
+            \x20       compadd -a candidates\n\
+            \x20   fi\n\
+            }}\n\
+            compdef _{func} {name}\n",
+            func = func,
+            name = name,
+            cc = COMPLETE_COMMAND,
+        ),
+        Shell::Fish => format!(
+            "function __{func}_complete\n\
+            \x20   set -l words (commandline -opc)\n\
+            \x20   set -e words[1]\n\
+            \x20   set -l cur (commandline -ct)\n\
+            \x20   {name} {cc} $words \"$cur\" 2>/dev/null\n\
+            end\n\
+            complete -c {name} -f -a '(__{func}_complete)'\n",
+            func = func,
+            name = name,
+            cc = COMPLETE_COMMAND,
+        ),
+    }
+}
+
+pub(crate) async fn complete<C: LevelContext>(
+    mut level: Level<C>,
+    func: Caller<C>,
+) -> u8 {
+    let mut words = if let Some(args) = level.args.take() {
+        args
+    } else {
+        std::env::args_os()
+            .skip(1)
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    };
+    words.remove(0);
+    let partial = words.pop().unwrap_or_default();
+
+    /*
+     * Anything the handlers write themselves would get mixed up with the
+     * candidate list, so it is discarded.
+     */
+    let mut stdout = level.stdout();
+    let d = Describer::default();
+    level.args = Some(words);
+    level.shared.describe = Some(d.clone());
+    level.shared.stdout = Output::capture();
+    level.shared.stderr = Output::capture();
+
+    /*
+     * Errors from the handlers are of no use to the shell, so we just
+     * provide whatever candidates we can.
+     */
+    func(level).await.ok();
+
+    if let Some(info) = d.levels().last() {
+        for c in candidates(info, &partial) {
+            writeln!(stdout, "{}", c).ok();
+        }
+    }
+    0
+}
+
+pub(crate) fn candidates(info: &LevelInfo, partial: &str) -> Vec<String> {
+    let free = if let Some(free) = &info.free {
+        free
+    } else {
+        /*
+         * If option parsing failed because the last word is an option that
+         * requires a value, we can try to complete the value.
+         */
+        return info
+            .args
+            .last()
+            .and_then(|prev| {
+                info.options.iter().find(|o| o.hasarg && o.named_by(prev))
+            })
+            .map(|opt| values(info, opt, partial))
+            .unwrap_or_default();
+    };
+
+    let mut out = Vec::new();
+    if partial.starts_with('-') {
+        for opt in info.options.iter() {
+            if !opt.short.is_empty() {
+                out.push(format!("-{}", opt.short));
+            }
+            if !opt.long.is_empty() {
+                out.push(format!("--{}", opt.long));
+            }
+        }
+    } else if free.is_empty() {
+        for cmd in info.commands.iter().filter(|c| c.visible) {
+            out.push(cmd.name.to_string());
+            out.extend(cmd.aliases.iter().cloned());
+        }
+        if !info.commands.is_empty()
+            && !info.commands.iter().any(|c| c.name == HELP_COMMAND)
+        {
+            out.push(HELP_COMMAND.to_string());
+        }
+    }
+
+    out.retain(|c| c.starts_with(partial));
+    out
+}
+
+fn values(info: &LevelInfo, opt: &OptionInfo, partial: &str) -> Vec<String> {
+    if !opt.choices.is_empty() {
+        return opt
+            .choices
+            .iter()
+            .filter(|c| c.starts_with(partial))
+            .cloned()
+            .collect();
+    }
+    if !opt.columns {
+        return Vec::new();
+    }
+
+    /*
+     * Column lists are separated by commas, so complete the last column name
+     * in the list.
+     */
+    let (done, last) = match partial.rfind(',') {
+        Some(i) => partial.split_at(i + 1),
+        None => ("", partial),
+    };
+    info.columns
+        .iter()
+        .filter(|c| c.name.starts_with(last))
+        .map(|c| format!("{}{}", done, c.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{script, Shell};
+    use crate::prelude::*;
+    use crate::testing::invoke;
+    use anyhow::Result;
+
+    async fn do_list(mut l: Level<()>) -> Result<()> {
+        l.add_column("name", 8, true);
+        l.add_column("number", 6, false);
+        l.add_column("size", 6, false);
+        l.optchoice("", "state", "state of things", &["running", "stopped"]);
+        no_args!(l);
+        writeln!(l.stdout(), "listed")?;
+        Ok(())
+    }
+
+    async fn do_thing(mut l: Level<()>) -> Result<()> {
+        l.cmda("list", "ls", "list things", cmd!(do_list))?;
+        l.hcmd("lost", "hidden command", cmd!(do_list))?;
+        l.default_cmd("list")?;
+        sel!(l).run().await
+    }
+
+    async fn do_top(mut l: Level<()>) -> Result<()> {
+        l.cmda("thing", "th", "manage things", cmd!(do_thing))?;
+        l.cmd("think", "think about things", cmd!(do_thing))?;
+        l.optflag("x", "extend", "extend");
+        let s = sel!(l);
+        writeln!(s.stdout(), "selected")?;
+        s.run().await
+    }
+
+    async fn complete(words: &[&str]) -> String {
+        let mut args = vec!["top", "__complete"];
+        args.extend_from_slice(words);
+        let res = invoke(cmd!(do_top), &args).await;
+        assert!(res.success());
+        res.stdout().to_string()
+    }
+
+    #[tokio::test]
+    async fn complete_commands() {
+        assert_eq!(complete(&[""]).await, "thing\nth\nthink\nhelp\n");
+        assert_eq!(complete(&["thin"]).await, "thing\nthink\n");
+        assert_eq!(complete(&["-x", "th", "l"]).await, "list\nls\n");
+        assert_eq!(complete(&["bogus", ""]).await, "");
+        assert_eq!(complete(&["help", "th", "l"]).await, "list\nls\n");
+    }
+
+    #[tokio::test]
+    async fn complete_options() {
+        assert_eq!(complete(&["--"]).await, "--help\n--extend\n");
+        assert_eq!(complete(&["th", "ls", "-p"]).await, "-p\n");
+    }
+
+    #[tokio::test]
+    async fn complete_columns() {
+        assert_eq!(
+            complete(&["th", "ls", "-o", ""]).await,
+            "name\nnumber\nsize\n"
+        );
+        assert_eq!(complete(&["th", "ls", "-Hs", "n"]).await, "name\nnumber\n");
+        assert_eq!(
+            complete(&["th", "ls", "-o", "size,n"]).await,
+            "size,name\nsize,number\n"
+        );
+
+        /*
+         * Options for the default command are completed without naming it.
+         */
+        assert_eq!(complete(&["th", "-o", "n"]).await, "name\nnumber\n");
+    }
+
+    #[tokio::test]
+    async fn complete_choices() {
+        assert_eq!(
+            complete(&["th", "ls", "--state", ""]).await,
+            "running\nstopped\n"
+        );
+        assert_eq!(complete(&["th", "ls", "--state", "s"]).await, "stopped\n");
+    }
+
+    #[test]
+    fn bash_script() {
+        let s = script("bash".parse().unwrap(), "my-tool");
+        assert!(s.contains("complete -o default -F _my_tool my-tool\n"));
+        assert!("csh".parse::<Shell>().is_err());
+    }
+}
@@ -0,0 +1,360 @@
+/*!
+ * Configuration files, which provide default option values for each level of
+ * the command tree.  The file is in TOML: each table is keyed by the path to a
+ * level below the top-level command, and each key within the table is the
+ * name of an option at that level; e.g.,
+ *
+ * ```text
+ * profile = "staging"
+ *
+ * [thing.list]
+ * o = "name,size"
+ * s = "size"
+ * ```
+ *
+ * Keys before the first table apply to the top-level command.  Values may be
+ * strings, numbers, booleans (for flags), or arrays of strings or numbers (for
+ * options that may be repeated).
+ *
+ * The "alias" table, and any table below it, instead defines command aliases
+ * for the top-level command, or for the level at the rest of the path.  Each
+ * alias is a command line, split into words as a shell would, or an array of
+ * words; e.g.,
+ *
+ * ```text
+ * [alias]
+ * ls = "thing list -o name,number -s name"
+ *
+ * [alias.thing]
+ * first = ["list", "-o", "name"]
+ * ```
+ *
+ * If the top-level command has a sub-command named "alias", these tables
+ * instead hold option values for that command and its sub-commands, like any
+ * other table, and there are no command aliases.
+ */
+
+use super::words;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub(crate) const ALIAS_TABLE: &str = "alias";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    Str(String),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Config {
+    path: PathBuf,
+    tables: BTreeMap<String, Vec<(String, Value)>>,
+    aliases: BTreeMap<String, Vec<(String, Vec<String>)>>,
+}
+
+impl Config {
+    /**
+     * Load the configuration file at `path`.  A file that does not exist is
+     * treated as if it were empty.  If `aliases` is false, the "alias" tables
+     * are treated as ordinary tables.
+     */
+    pub fn load(path: &Path, aliases: bool) -> Result<Config> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => bail!("config file {}: {}", path.display(), e),
+        };
+        let mut c = Config::parse(&text, aliases)
+            .map_err(|e| anyhow!("config file {}: {:#}", path.display(), e))?;
+        c.path = path.to_path_buf();
+        Ok(c)
+    }
+
+    pub fn parse(text: &str, aliases: bool) -> Result<Config> {
+        let root = text.parse::<toml::Table>().map_err(|e| {
+            /*
+             * Report the position of a syntax error on the same line as the
+             * message, rather than with an excerpt from the file.
+             */
+            let before = &text[..e.span().map(|s| s.start).unwrap_or(0)];
+            let line = before.matches('\n').count() + 1;
+            let column =
+                before.rsplit('\n').next().unwrap().chars().count() + 1;
+            anyhow!("line {}, column {}: {}", line, column, e.message())
+        })?;
+        let mut c = Config::default();
+        c.add_table(&mut Vec::new(), &root, aliases)?;
+        Ok(c)
+    }
+
+    /**
+     * Record the values in `table`, which is at `path` in the file, and then
+     * those in each table nested within it.
+     */
+    fn add_table(
+        &mut self,
+        path: &mut Vec<String>,
+        table: &toml::Table,
+        aliases: bool,
+    ) -> Result<()> {
+        let level = alias_level(path).filter(|_| aliases);
+        let mut pairs = Vec::new();
+        let mut alias_pairs = Vec::new();
+
+        for (key, value) in table.iter() {
+            path.push(key.to_string());
+            if let toml::Value::Table(t) = value {
+                self.add_table(path, t, aliases)?;
+            } else {
+                let res = if level.is_some() {
+                    value_of(value)
+                        .and_then(alias_words)
+                        .map(|words| alias_pairs.push((key.to_string(), words)))
+                } else {
+                    value_of(value)
+                        .map(|value| pairs.push((key.to_string(), value)))
+                };
+                res.with_context(|| format!("key \"{}\"", path.join(".")))?;
+            }
+            path.pop();
+        }
+
+        if let Some(level) = level {
+            self.aliases.insert(level, alias_pairs);
+        } else {
+            self.tables.insert(path.join("."), pairs);
+        }
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+     * The key-value pairs for the level with the nominated path below the
+     * top-level command, in the order they appear in the file.
+     */
+    pub fn table(&self, path: &[String]) -> &[(String, Value)] {
+        self.tables
+            .get(&path.join("."))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /**
+     * The command aliases for the level with the nominated path below the
+     * top-level command, in the order they appear in the file.  Each alias is
+     * the list of words it expands to.
+     */
+    pub fn aliases(&self, path: &[String]) -> &[(String, Vec<String>)] {
+        self.aliases
+            .get(&path.join("."))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+/**
+ * If the table at `path` holds command aliases, determine the path of the
+ * level to which they apply.
+ */
+fn alias_level(path: &[String]) -> Option<String> {
+    match path.split_first() {
+        Some((first, rest)) if first == ALIAS_TABLE => Some(rest.join(".")),
+        _ => None,
+    }
+}
+
+fn alias_words(value: Value) -> Result<Vec<String>> {
+    let words = match value {
+        Value::Str(s) => words::split(&s)?,
+        Value::List(l) => l,
+        Value::Bool(_) => bail!("an alias must be a string or an array"),
+    };
+    if words.is_empty() {
+        bail!("an alias must not be empty");
+    }
+    Ok(words)
+}
+
+/**
+ * Convert a value from the file into one that can be passed to an option.
+ * Numbers are kept as strings, as they will be passed to an option like any
+ * other value.
+ */
+fn value_of(value: &toml::Value) -> Result<Value> {
+    fn scalar(value: &toml::Value) -> Option<String> {
+        match value {
+            toml::Value::String(s) => Some(s.to_string()),
+            toml::Value::Integer(i) => Some(i.to_string()),
+            toml::Value::Float(f) => Some(f.to_string()),
+            _ => None,
+        }
+    }
+
+    Ok(match value {
+        toml::Value::Boolean(b) => Value::Bool(*b),
+        toml::Value::Array(items) => Value::List(
+            items
+                .iter()
+                .map(|item| {
+                    scalar(item).ok_or_else(|| {
+                        anyhow!("arrays may only contain strings and numbers")
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        value => Value::Str(scalar(value).ok_or_else(|| {
+            anyhow!("values must be strings, numbers, booleans, or arrays")
+        })?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Value};
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn config_parse() {
+        let c = Config::parse(
+            "# defaults for everything\n\
+            profile = \"staging\" # trailing comment\n\
+            \n\
+            [thing.list]\n\
+            o = 'name,size'\n\
+            s = \"si#ze\"\n\
+            H = true\n\
+            \"count\" = -12\n\
+            tag = [\"a\", 2, 'c',]\n",
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            c.table(&path("")),
+            &[("profile".to_string(), Value::Str("staging".to_string()))]
+        );
+        assert_eq!(
+            c.table(&path("thing.list")),
+            &[
+                ("o".to_string(), Value::Str("name,size".to_string())),
+                ("s".to_string(), Value::Str("si#ze".to_string())),
+                ("H".to_string(), Value::Bool(true)),
+                ("count".to_string(), Value::Str("-12".to_string())),
+                (
+                    "tag".to_string(),
+                    Value::List(vec![
+                        "a".to_string(),
+                        "2".to_string(),
+                        "c".to_string()
+                    ])
+                ),
+            ]
+        );
+        assert!(c.table(&path("thing")).is_empty());
+
+        /*
+         * Any form of TOML that results in the same tables is accepted.
+         */
+        let c = Config::parse(
+            "[thing]\n\
+            list.o = '''name,size'''\n\
+            list.tag = [\n  \"a\", # first\n  2,\n]\n\
+            get = { H = true, s = \"\"\"\nsize\"\"\" }\n",
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            c.table(&path("thing.list")),
+            &[
+                ("o".to_string(), Value::Str("name,size".to_string())),
+                (
+                    "tag".to_string(),
+                    Value::List(vec!["a".to_string(), "2".to_string()])
+                ),
+            ]
+        );
+        assert_eq!(
+            c.table(&path("thing.get")),
+            &[
+                ("H".to_string(), Value::Bool(true)),
+                ("s".to_string(), Value::Str("size".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_aliases() {
+        let c = Config::parse(
+            "profile = \"staging\"\n\
+            [alias]\n\
+            ls = \"thing list -o 'name,number'\"\n\
+            [alias.thing]\n\
+            first = [\"list\", \"-o\", \"name\"]\n",
+            true,
+        )
+        .unwrap();
+
+        let words = |w: &[&str]| w.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            c.aliases(&path("")),
+            &[(
+                "ls".to_string(),
+                words(&["thing", "list", "-o", "name,number"])
+            )]
+        );
+        assert_eq!(
+            c.aliases(&path("thing")),
+            &[("first".to_string(), words(&["list", "-o", "name"]))]
+        );
+        assert!(c.aliases(&path("thing.list")).is_empty());
+        assert!(c.table(&path("alias")).is_empty());
+        assert_eq!(c.table(&path("")).len(), 1);
+
+        for (text, msg) in [
+            ("[alias]\nls = true\n", "must be a string or an array"),
+            ("[alias]\nls = \"\"\n", "must not be empty"),
+            ("[alias]\nls = \"'a\"\n", "unterminated single quote"),
+        ] {
+            let e = Config::parse(text, true).unwrap_err();
+            assert!(format!("{:#}", e).contains(msg), "{}: {:#}", text, e);
+        }
+
+        /*
+         * Without aliases, the tables are like any other.
+         */
+        let c = Config::parse("[alias]\nls = true\n", false).unwrap();
+        assert!(c.aliases(&path("")).is_empty());
+        assert_eq!(
+            c.table(&path("alias")),
+            &[("ls".to_string(), Value::Bool(true))]
+        );
+    }
+
+    #[test]
+    fn config_errors() {
+        for (text, msg) in [
+            ("[thing\n", "line 1, column 7: invalid table header"),
+            ("a = 1\na = 2\n", "line 2, column 1: duplicate key `a`"),
+            ("[a]\n[a]\n", "line 2, column 1: invalid table header"),
+            ("a = \"open\n", "line 1, column 10: invalid basic string"),
+            ("a = 1979-05-27\n", "key \"a\": values must be"),
+            ("[t]\na = [true]\n", "key \"t.a\": arrays may only contain"),
+            ("just words\n", "line 1, column 6: expected `.`, `=`"),
+        ] {
+            let e = Config::parse(text, true).unwrap_err();
+            assert!(format!("{:#}", e).contains(msg), "{}: {:#}", text, e);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+/*!
+ * When describing the command tree, level handlers are run in a mode where
+ * the argument parsing routines record what has been registered for each
+ * level, rather than processing arguments and returning control to the rest of
+ * the handler.
+ *
+ * The describer either follows a single path through the tree, as nominated
+ * by the arguments, or walks every visible command in the tree.  When walking
+ * the tree, each level hands the context object back through the describer
+ * when it is dropped, once the handler is done with it, so that it can be
+ * passed to the next handler.  Levels below a command registered with
+ * `Level::cmd_with()` have no context object, as one cannot be produced while
+ * describing the tree.
+ */
+
+use super::{
+    Caller, Level, LevelContext, OptionInfo, Output, Positional, Shared,
+};
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub(crate) struct CommandSummary {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub hidden_aliases: Vec<String>,
+    pub desc: String,
+    pub visible: bool,
+    pub default: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct LevelInfo {
+    pub names: Vec<String>,
+    pub synopsis: String,
+    /**
+     * The arguments passed to this level, which will include any options as
+     * well as positional arguments.
+     */
+    pub args: Vec<String>,
+    /**
+     * The positional arguments for this level, if option parsing succeeded.
+     */
+    pub free: Option<Vec<String>>,
+    pub commands: Vec<CommandSummary>,
+    pub options: Vec<OptionInfo>,
+    pub positionals: Vec<Positional>,
+    /*
+     * Constraints on the options, each of which names options by their short
+     * and long names.
+     */
+    pub mutex: Vec<Vec<(String, String)>>,
+    pub requires: Vec<((String, String), (String, String))>,
+    pub at_least_one: Vec<Vec<(String, String)>>,
+    pub exactly_one: Vec<Vec<(String, String)>>,
+    pub columns: Vec<ColumnSummary>,
+}
+
+#[derive(Clone)]
+pub(crate) struct ColumnSummary {
+    pub name: String,
+    pub width: usize,
+    pub default: bool,
+}
+
+/**
+ * The failure of a handler to describe its level, or to hand back the context
+ * object, while walking the tree, which prevents the rest of the tree from
+ * being visited.  Handlers above the one that failed return this error as
+ * they found it.
+ */
+#[derive(Debug)]
+pub(crate) struct Incomplete(String);
+
+impl std::fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Incomplete {}
+
+impl Incomplete {
+    pub fn error(names: &[String], res: anyhow::Result<()>) -> anyhow::Error {
+        match res {
+            Err(e) if e.is::<Incomplete>() => e,
+            Err(e) => Incomplete(format!(
+                "could not describe \"{}\": {}",
+                names.join(" "),
+                e
+            ))
+            .into(),
+            Ok(()) => Incomplete(format!(
+                "could not describe \"{}\": the handler did not parse its \
+                arguments",
+                names.join(" ")
+            ))
+            .into(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    levels: Vec<LevelInfo>,
+    context: Option<Box<dyn Any + Send>>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Describer {
+    tree: bool,
+    hidden: bool,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Describer {
+    /**
+     * Create a describer that will walk every visible command in the tree,
+     * and every hidden command as well if `hidden` is true.
+     */
+    pub fn tree(hidden: bool) -> Describer {
+        Describer {
+            tree: true,
+            hidden,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.tree
+    }
+
+    /**
+     * Determine whether a command should be visited when walking the tree.
+     */
+    pub fn visits(&self, visible: bool) -> bool {
+        visible || self.hidden
+    }
+
+    pub fn record(&self, info: LevelInfo) {
+        self.inner.lock().unwrap().levels.push(info);
+    }
+
+    pub fn levels(&self) -> Vec<LevelInfo> {
+        self.inner.lock().unwrap().levels.clone()
+    }
+
+    /**
+     * Determine whether the level with these names has been recorded.
+     */
+    pub fn recorded(&self, names: &[String]) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .levels
+            .iter()
+            .any(|l| l.names == names)
+    }
+
+    /**
+     * Check the result of the handler for the level with these names, which
+     * must have recorded its level to be of use.
+     */
+    pub fn complete(
+        &self,
+        names: &[String],
+        res: anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        match res {
+            Err(e) if e.is::<Incomplete>() => Err(e),
+            res if !self.recorded(names) => Err(Incomplete::error(names, res)),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Hand back the context object for a level as the level is dropped.
+     */
+    pub fn stash<C: Any + Send>(&self, context: C) {
+        self.inner.lock().unwrap().context = Some(Box::new(context));
+    }
+
+    /**
+     * Take the context object handed back by the level that was just visited,
+     * or `None` if that level did not hand one back.
+     */
+    pub fn take<C: Any + Send>(&self) -> Option<C> {
+        self.inner
+            .lock()
+            .unwrap()
+            .context
+            .take()
+            .and_then(|c| c.downcast::<C>().ok())
+            .map(|c| *c)
+    }
+}
+
+impl LevelInfo {
+    /**
+     * Locate the description of this level, which is the description of the
+     * command that selects it in the level above.
+     */
+    pub fn desc<'a>(&self, levels: &'a [LevelInfo]) -> Option<&'a str> {
+        let (name, parent) = self.names.split_last()?;
+        levels
+            .iter()
+            .find(|l| l.names == parent)?
+            .commands
+            .iter()
+            .find(|c| &c.name == name)
+            .map(|c| c.desc.as_str())
+    }
+}
+
+/**
+ * Walk the whole command tree below the top-level handler `func`, and return
+ * a description of every level that was visited.  Hidden commands are only
+ * visited if `hidden` is true.  Rather than present part of the tree as if it
+ * were the whole, this fails if the walk was incomplete.
+ */
+pub(crate) async fn walk<C: LevelContext>(
+    name: &str,
+    private: C,
+    func: Caller<C>,
+    hidden: bool,
+) -> anyhow::Result<Vec<LevelInfo>> {
+    let (levels, _) = walk_context(name, private, func, hidden).await?;
+    Ok(levels)
+}
+
+/**
+ * Walk the tree as per `walk()`, and also return the context object once it
+ * is handed back by the top level of the tree.
+ */
+pub(crate) async fn walk_context<C: LevelContext>(
+    name: &str,
+    private: C,
+    func: Caller<C>,
+    hidden: bool,
+) -> anyhow::Result<(Vec<LevelInfo>, C)> {
+    let d = Describer::tree(hidden);
+    let names = [name.to_string()];
+    let res = start(&d, name, private, func).await;
+    let private = d.take::<C>();
+    d.complete(&names, res)?;
+    match private {
+        Some(c) => Ok((d.levels(), c)),
+        None => Err(Incomplete::error(
+            &names,
+            Err(anyhow::anyhow!(
+                "the handler did not hand back the context object"
+            )),
+        )),
+    }
+}
+
+async fn start<C: LevelContext>(
+    d: &Describer,
+    name: &str,
+    private: C,
+    func: Caller<C>,
+) -> anyhow::Result<()> {
+    let shared = Shared {
+        stdout: Output::capture(),
+        stderr: Output::capture(),
+        describe: Some(d.clone()),
+        ..Default::default()
+    };
+    let l = Level::new_sub(
+        vec![name.to_string()],
+        private,
+        Some(Vec::new()),
+        shared,
+    );
+    func(l).await
+}
+
+/**
+ * Render the visible commands in a walked tree, indenting the sub-commands
+ * of each command beneath it.  The first recorded level is the top of the
+ * tree.
+ */
+pub(crate) fn hierarchy(levels: &[LevelInfo]) -> String {
+    fn visit(
+        levels: &[LevelInfo],
+        info: &LevelInfo,
+        depth: usize,
+        lines: &mut Vec<(String, String)>,
+    ) {
+        for cmd in info.commands.iter().filter(|c| c.visible) {
+            let mut label = format!("{}{}", "    ".repeat(depth), cmd.name);
+            if !cmd.aliases.is_empty() {
+                label.push_str(&format!(" ({})", cmd.aliases.join(", ")));
+            }
+            lines.push((label, cmd.desc.to_string()));
+
+            let mut names = info.names.clone();
+            names.push(cmd.name.to_string());
+            if let Some(sub) = levels.iter().find(|l| l.names == names) {
+                visit(levels, sub, depth + 1, lines);
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    if let Some(top) = levels.first() {
+        visit(levels, top, 0, &mut lines);
+    }
+
+    let width = lines
+        .iter()
+        .map(|(l, _)| l.len())
+        .max()
+        .unwrap_or(0)
+        .max(19);
+    let mut out = "Commands:\n".to_string();
+    for (label, desc) in lines {
+        out.push_str(&format!("    {:<w$} {}\n", label, desc, w = width));
+    }
+    out
+}
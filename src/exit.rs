@@ -0,0 +1,105 @@
+/*!
+ * Exit status for errors returned by handlers.  When an error is reported by
+ * `hiercmd::run()`, the process exits with status 2 for a `UsageError` and 1
+ * for most other errors.  A handler may nominate a different status for an
+ * error, so that scripts can distinguish between different kinds of failure;
+ * e.g.,
+ *
+ * ```ignore
+ * let res = client.fetch(&name).await.exit_code(3)?;
+ * ```
+ */
+
+use anyhow::Result;
+
+/**
+ * An error, along with the exit status the process should have when the error
+ * is reported.  The error is otherwise reported as it would have been without
+ * a nominated status; i.e., this type has the same message and cause chain as
+ * the error it wraps.
+ */
+#[derive(Debug)]
+pub struct ExitError {
+    code: u8,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for ExitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for ExitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl ExitError {
+    /**
+     * Wrap `error` such that reporting it results in exit status `code`.  A
+     * status of 0 would suggest the command succeeded, so it is reported as 1
+     * instead.
+     */
+    pub fn new<E: Into<anyhow::Error>>(code: u8, error: E) -> ExitError {
+        ExitError {
+            code: if code == 0 { 1 } else { code },
+            error: error.into(),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    /**
+     * The wrapped error.
+     */
+    pub fn error(&self) -> &anyhow::Error {
+        &self.error
+    }
+}
+
+/**
+ * Nominate an exit status for the error in a `Result`, as per `ExitError`.
+ */
+pub trait WithExitCode<T> {
+    fn exit_code(self, code: u8) -> Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> WithExitCode<T> for Result<T, E> {
+    fn exit_code(self, code: u8) -> Result<T> {
+        self.map_err(|e| ExitError::new(code, e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExitError, WithExitCode};
+    use anyhow::{anyhow, Context, Result};
+
+    #[test]
+    fn exit_codes() {
+        let res: Result<()> = Err(anyhow!("connection refused"))
+            .context("could not list things")
+            .exit_code(3);
+        let e = res.unwrap_err();
+        let ee = e.downcast_ref::<ExitError>().unwrap();
+        assert_eq!(ee.code(), 3);
+        let chain = e.chain().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(chain, ["could not list things", "connection refused"]);
+
+        /*
+         * The status is still found if more context is added later.
+         */
+        let e = Err::<(), _>(e).context("sync failed").unwrap_err();
+        assert_eq!(e.downcast_ref::<ExitError>().unwrap().code(), 3);
+
+        let e: Result<()> = Err(std::fmt::Error).exit_code(0);
+        assert_eq!(
+            e.unwrap_err().downcast_ref::<ExitError>().unwrap().code(),
+            1
+        );
+    }
+}
@@ -0,0 +1,92 @@
+/*!
+ * Hooks that run around the dispatch of each command, as registered with
+ * `Level::around_run()` and friends.  Hooks are inherited by every level below
+ * the one at which they are registered, so a hook registered at the top level
+ * runs once for each command selected on the way down; e.g., for both "thing"
+ * and "thing list".
+ */
+
+use super::{Globals, HandlerFuture};
+use std::sync::Arc;
+
+/**
+ * A description of the command being dispatched, as provided to hooks.
+ */
+#[derive(Clone)]
+pub struct Dispatch {
+    pub(crate) names: Vec<String>,
+    pub(crate) desc: String,
+    pub(crate) external: bool,
+    pub(crate) matches: Arc<getopts::Matches>,
+    pub(crate) globals: Globals,
+}
+
+impl Dispatch {
+    /**
+     * The names of each level, from the command name down to and including
+     * the command being dispatched.
+     */
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /**
+     * The name of the command being dispatched.
+     */
+    pub fn command(&self) -> &str {
+        self.names.last().unwrap()
+    }
+
+    /**
+     * The description of the command, as registered with the `cmd()` family
+     * of methods; empty for an external command.
+     */
+    pub fn desc(&self) -> &str {
+        &self.desc
+    }
+
+    /**
+     * Whether the command is an external command, rather than one with a
+     * registered handler.
+     */
+    pub fn is_external(&self) -> bool {
+        self.external
+    }
+
+    /**
+     * The options parsed at the level that selected the command.
+     */
+    pub fn opts(&self) -> &getopts::Matches {
+        &self.matches
+    }
+
+    /**
+     * The values of global options provided at the level that selected the
+     * command and above.
+     */
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+}
+
+/**
+ * A hook, which is passed the future that will run the command (and any
+ * hooks registered after this one) and returns a future to run in its place.
+ */
+pub(crate) type Hook =
+    Arc<dyn Fn(Dispatch, HandlerFuture) -> HandlerFuture + Send + Sync>;
+
+/**
+ * Wrap the future that runs a command in each hook, such that the first hook
+ * registered is the outermost.
+ */
+pub(crate) fn wrap(
+    hooks: &[Hook],
+    dispatch: Dispatch,
+    run: HandlerFuture,
+) -> HandlerFuture {
+    hooks
+        .iter()
+        .rev()
+        .fold(run, |next, hook| hook(dispatch.clone(), next))
+}
@@ -0,0 +1,315 @@
+/*!
+ * A machine-readable description of the command tree, in JSON.  Unlike the
+ * built-in help command and the manual pages, hidden commands are included in
+ * the description so that it can be used for consistency checks across the
+ * whole tree.
+ */
+
+use super::describe::{walk, LevelInfo};
+use super::{caller, Arity, Level, LevelContext};
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::future::Future;
+
+/**
+ * Describe the command tree below the top-level handler `func`, where `name`
+ * is the command name and `private` is the context object to pass to the
+ * handlers.  Each level is an object with its command names, synopsis,
+ * sub-commands, options, option constraints, positional arguments, and table
+ * columns; the level for each sub-command is nested within the description of
+ * that command.  If a handler fails, or does not parse its arguments, the tree
+ * cannot be walked in full and an error is returned instead.
+ */
+pub async fn tree<C, F, Fut>(name: &str, private: C, func: F) -> Result<Value>
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let levels = walk(name, private, caller(func), true).await?;
+    Ok(levels
+        .first()
+        .map(|top| level(&levels, top))
+        .unwrap_or(Value::Null))
+}
+
+fn name_or_null(name: &str) -> Value {
+    if name.is_empty() {
+        Value::Null
+    } else {
+        Value::from(name)
+    }
+}
+
+fn option_pair((short, long): &(String, String)) -> Value {
+    json!({
+        "short": name_or_null(short),
+        "long": name_or_null(long),
+    })
+}
+
+fn option_groups(groups: &[Vec<(String, String)>]) -> Value {
+    groups
+        .iter()
+        .map(|group| group.iter().map(option_pair).collect::<Vec<_>>())
+        .collect()
+}
+
+fn level(levels: &[LevelInfo], info: &LevelInfo) -> Value {
+    let commands = info
+        .commands
+        .iter()
+        .map(|cmd| {
+            let mut out = json!({
+                "name": cmd.name,
+                "aliases": cmd.aliases,
+                "hidden_aliases": cmd.hidden_aliases,
+                "visible": cmd.visible,
+                "default": cmd.default,
+                "description": cmd.desc,
+            });
+
+            let mut names = info.names.clone();
+            names.push(cmd.name.to_string());
+            if let Some(sub) = levels.iter().find(|l| l.names == names) {
+                out["level"] = level(levels, sub);
+            }
+            out
+        })
+        .collect::<Vec<_>>();
+
+    let options = info
+        .options
+        .iter()
+        .filter(|opt| !opt.inherited)
+        .map(|opt| {
+            json!({
+                "short": name_or_null(&opt.short),
+                "long": name_or_null(&opt.long),
+                "description": opt.desc,
+                "hint": if opt.hasarg { Value::from(opt.hint.as_str()) }
+                    else { Value::Null },
+                "takes_value": opt.hasarg,
+                "multiple": opt.multi,
+                "required": opt.required,
+                "global": opt.global,
+                "env": opt.env,
+                "choices": opt.choices,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let requires = info
+        .requires
+        .iter()
+        .map(|(opt, needs)| {
+            json!({
+                "option": option_pair(opt),
+                "needs": option_pair(needs),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let positionals = info
+        .positionals
+        .iter()
+        .map(|p| {
+            let (arity, min, max) = match p.arity {
+                Arity::Required => ("required", 1, Some(1)),
+                Arity::Optional => ("optional", 0, Some(1)),
+                Arity::Variadic { min, max } => ("variadic", min, max),
+            };
+            json!({
+                "name": p.name,
+                "description": p.desc,
+                "arity": arity,
+                "min": min,
+                "max": max,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let columns = info
+        .columns
+        .iter()
+        .map(|col| {
+            json!({
+                "name": col.name,
+                "width": col.width,
+                "default": col.default,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "names": info.names,
+        "synopsis": info.synopsis,
+        "commands": commands,
+        "options": options,
+        "mutually_exclusive": option_groups(&info.mutex),
+        "requires": requires,
+        "at_least_one_of": option_groups(&info.at_least_one),
+        "exactly_one_of": option_groups(&info.exactly_one),
+        "positionals": positionals,
+        "columns": columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tree;
+    use crate::prelude::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    async fn do_list(mut l: Level<()>) -> Result<()> {
+        l.add_column("name", 8, true);
+        l.add_column("size", 6, false);
+        l.optflag("a", "all", "list all things");
+        l.optopt("", "kind", "kind of thing", "KIND");
+        l.mutually_exclusive(&[("a", "all"), ("", "kind")]);
+        l.optopt("", "owner", "owner of things", "OWNER");
+        l.requires(("", "owner"), ("", "kind"));
+        l.at_least_one_of(&[("", "kind"), ("", "owner")]);
+        l.exactly_one_of(&[("a", "all"), ("", "owner")]);
+        no_args!(l);
+        Ok(())
+    }
+
+    async fn do_get(mut l: Level<()>) -> Result<()> {
+        l.positional("NAME", "name of thing", Arity::Required)?;
+        l.positional(
+            "FIELD",
+            "fields to show",
+            Arity::Variadic {
+                min: 0,
+                max: Some(3),
+            },
+        )?;
+        l.parse()?;
+        Ok(())
+    }
+
+    async fn do_top(mut l: Level<()>) -> Result<()> {
+        l.cmda("list", "ls", "list things", cmd!(do_list))?;
+        l.halias("list", "enumerate")?;
+        l.hcmd("secret", "hidden command", cmd!(do_list))?;
+        l.cmd("get", "get a thing", cmd!(do_get))?;
+        sel!(l).run().await
+    }
+
+    async fn do_broken(_: Level<()>) -> Result<()> {
+        anyhow::bail!("no connection")
+    }
+
+    async fn do_broken_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("list", "list things", cmd!(do_list))?;
+        l.cmd("broken", "fail to describe", cmd!(do_broken))?;
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn json_tree() {
+        let t = tree("top", (), cmd!(do_top)).await.unwrap();
+
+        assert_eq!(t["names"], json!(["top"]));
+        assert_eq!(t["synopsis"], "top COMMAND [ARGS...]");
+
+        let list = &t["commands"][0];
+        assert_eq!(list["name"], "list");
+        assert_eq!(list["aliases"], json!(["ls"]));
+        assert_eq!(list["hidden_aliases"], json!(["enumerate"]));
+        assert_eq!(list["visible"], true);
+        assert_eq!(list["description"], "list things");
+
+        let level = &list["level"];
+        assert_eq!(level["names"], json!(["top", "list"]));
+        assert_eq!(
+            level["columns"],
+            json!([
+                { "name": "name", "width": 8, "default": true },
+                { "name": "size", "width": 6, "default": false },
+            ])
+        );
+        assert_eq!(
+            level["options"][7],
+            json!({
+                "short": null,
+                "long": "kind",
+                "description": "kind of thing",
+                "hint": "KIND",
+                "takes_value": true,
+                "multiple": false,
+                "required": false,
+                "global": false,
+                "env": null,
+                "choices": [],
+            })
+        );
+        assert_eq!(
+            level["mutually_exclusive"],
+            json!([[
+                { "short": "a", "long": "all" },
+                { "short": null, "long": "kind" },
+            ]])
+        );
+
+        assert_eq!(
+            level["requires"],
+            json!([{
+                "option": { "short": null, "long": "owner" },
+                "needs": { "short": null, "long": "kind" },
+            }])
+        );
+        assert_eq!(
+            level["at_least_one_of"],
+            json!([[
+                { "short": null, "long": "kind" },
+                { "short": null, "long": "owner" },
+            ]])
+        );
+        assert_eq!(
+            level["exactly_one_of"],
+            json!([[
+                { "short": "a", "long": "all" },
+                { "short": null, "long": "owner" },
+            ]])
+        );
+        assert_eq!(level["positionals"], json!([]));
+
+        let secret = &t["commands"][1];
+        assert_eq!(secret["visible"], false);
+        assert_eq!(secret["level"]["names"], json!(["top", "secret"]));
+
+        let get = &t["commands"][2]["level"];
+        assert_eq!(get["synopsis"], "top get NAME [FIELD...]");
+        assert_eq!(
+            get["positionals"],
+            json!([
+                {
+                    "name": "NAME",
+                    "description": "name of thing",
+                    "arity": "required",
+                    "min": 1,
+                    "max": 1,
+                },
+                {
+                    "name": "FIELD",
+                    "description": "fields to show",
+                    "arity": "variadic",
+                    "min": 0,
+                    "max": 3,
+                },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn json_incomplete() {
+        let e = tree("top", (), cmd!(do_broken_top)).await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "could not describe \"top broken\": no connection"
+        );
+    }
+}
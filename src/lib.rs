@@ -1,13 +1,41 @@
 use anyhow::{bail, Result};
+use std::backtrace::BacktraceStatus;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
 
+mod blocking;
+pub mod complete;
+mod config;
+mod describe;
+mod exit;
+mod hook;
+pub mod json;
+mod logging;
+pub mod man;
+mod plugin;
+mod positional;
+#[cfg(feature = "repl")]
+pub mod repl;
+mod suggest;
 pub mod table;
+pub mod testing;
+mod units;
+mod words;
 
 pub mod prelude {
     pub use super::table::Row;
-    pub use super::{args, bad_args, cmd, no_args, sel, Level};
+    pub use super::{
+        args, bad_args, cmd, no_args, sel, Arity, HandlerFuture, Level,
+        UsageError, WithExitCode,
+    };
     pub use slog::{crit, debug, error, info, o, trace, warn, Logger};
+    pub use std::io::Write as _;
 }
 
 /**
@@ -19,18 +47,210 @@ pub trait LevelContext: Send + Sync + 'static {}
 
 impl<T: 'static> LevelContext for T where T: Send + Sync {}
 
-pub type Caller<C> =
-    fn(Level<C>) -> Pin<Box<(dyn Future<Output = Result<()>>)>>;
+pub use exit::{ExitError, WithExitCode};
+pub use hook::Dispatch;
+pub use plugin::PluginExit;
+pub use positional::{Arity, Positional};
+
+const HELP_COMMAND: &str = "help";
+const NO_CONFIG: &str = "no-config";
+const DEBUG_OPTION: &str = "debug";
+const NO_CONTEXT: &str = "context object is not available while describing \
+    the command tree below a command registered with Level::cmd_with()";
+
+/**
+ * The future returned by a level handler.  It must be `Send`, so that
+ * handlers may be spawned onto other threads.
+ *
+ * A handler that refers back to a handler above it (e.g., to start the
+ * interactive shell from the top-level handler) must do so through a function
+ * that returns this type, rather than an `async fn`; otherwise, the compiler
+ * cannot determine whether the futures are `Send`.
+ */
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/**
+ * A level handler, as registered with the `Level.cmd()` family of methods.
+ * Handlers are shared, rather than consumed, as the same handler may be
+ * called more than once; e.g., when walking the command tree for the
+ * built-in help command, or for each line in the interactive shell.
+ */
+pub type Caller<C> = Arc<dyn Fn(Level<C>) -> HandlerFuture + Send + Sync>;
+
+/**
+ * Wrap a handler function or closure for storage in a `Caller`.
+ */
+fn caller<C, F, Fut>(func: F) -> Caller<C>
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Arc::new(move |level| Box::pin(func(level)))
+}
+
+/**
+ * A command registered with `Level::cmd_with()`, which holds the handler for
+ * the level below it so that `Selection::run_with()` can run it with a
+ * context object of type `D`.
+ */
+pub struct Mapped<D: LevelContext> {
+    names: Vec<String>,
+    func: Caller<D>,
+}
 
 #[derive(Clone)]
 struct CommandInfo<C: LevelContext> {
     name: String,
-    alias: Option<String>,
+    aliases: Vec<String>,
+    hidden_aliases: Vec<String>,
     desc: String,
     func: Caller<C>,
     visible: bool,
+    /*
+     * Whether this command is run when the user does not nominate one.
+     */
+    default: bool,
+    /*
+     * Whether this command was registered with `cmd_with()`, and so must be
+     * run with `Selection::run_with()`.
+     */
+    mapped: bool,
+}
+
+impl<C: LevelContext> CommandInfo<C> {
+    fn matches(&self, want: &str) -> bool {
+        self.name == want
+            || self.aliases.iter().any(|a| a == want)
+            || self.hidden_aliases.iter().any(|a| a == want)
+    }
+}
+
+/**
+ * A record of each option registered for a level, as the getopts object does
+ * not allow us to enumerate them once they have been added.
+ */
+#[derive(Clone)]
+struct OptionInfo {
+    short: String,
+    long: String,
+    desc: String,
+    hint: String,
+    hasarg: bool,
+    multi: bool,
+    required: bool,
+    columns: bool,
+    /*
+     * Global options are passed down to every level below the one that
+     * declared them.  In those levels, the option is inherited.
+     */
+    global: bool,
+    inherited: bool,
+    /*
+     * An environment variable from which to take the value of the option if
+     * it is not provided on the command line.
+     */
+    env: Option<String>,
+    /*
+     * If not empty, the only values that the option will accept.
+     */
+    choices: Vec<String>,
+}
+
+impl OptionInfo {
+    fn pair(&self) -> OptionPair {
+        OptionPair {
+            short: self.short.to_string(),
+            long: self.long.to_string(),
+        }
+    }
+
+    /**
+     * The name used to look up this option in the parsed matches.
+     */
+    fn name(&self) -> &str {
+        if self.long.is_empty() {
+            &self.short
+        } else {
+            &self.long
+        }
+    }
+
+    /**
+     * The word that nominates this option on the command line.
+     */
+    fn flag(&self) -> String {
+        if self.long.is_empty() {
+            format!("-{}", self.short)
+        } else {
+            format!("--{}", self.long)
+        }
+    }
+
+    /**
+     * Produce the arguments that would provide a value from the configuration
+     * file for this option.
+     */
+    fn config_args(&self, value: &config::Value) -> Result<Vec<String>> {
+        Ok(match (value, self.hasarg) {
+            (config::Value::Bool(true), false) => vec![self.flag()],
+            (config::Value::Bool(false), false) => Vec::new(),
+            (config::Value::Str(s), true) => vec![self.flag(), s.to_string()],
+            (config::Value::List(l), true) if self.multi => l
+                .iter()
+                .flat_map(|s| [self.flag(), s.to_string()])
+                .collect(),
+            (config::Value::List(_), true) => {
+                bail!("option {} takes only one value", self.flag())
+            }
+            (_, true) => bail!("option {} requires a value", self.flag()),
+            (_, false) => {
+                bail!("option {} is a flag; use true or false", self.flag())
+            }
+        })
+    }
+
+    fn register(&self, options: &mut getopts::Options) {
+        let desc = if let Some(env) = &self.env {
+            format!("{} (env: {})", self.desc, env)
+        } else {
+            self.desc.to_string()
+        };
+        options.opt(
+            &self.short,
+            &self.long,
+            &desc,
+            &self.hint,
+            if self.hasarg {
+                getopts::HasArg::Yes
+            } else {
+                getopts::HasArg::No
+            },
+            if self.multi {
+                getopts::Occur::Multi
+            } else {
+                getopts::Occur::Optional
+            },
+        );
+    }
+
+    /**
+     * Determine if this option is named by a word from the command line; e.g.,
+     * "--long", "-s", or a cluster of short options like "-Hs" where this is
+     * the last option in the cluster.
+     */
+    fn named_by(&self, word: &str) -> bool {
+        if let Some(long) = word.strip_prefix("--") {
+            !self.long.is_empty() && self.long == long
+        } else if let Some(shorts) = word.strip_prefix('-') {
+            !self.short.is_empty() && shorts.ends_with(self.short.as_str())
+        } else {
+            false
+        }
+    }
 }
 
+#[derive(Clone)]
 struct OptionPair {
     short: String,
     long: String,
@@ -56,11 +276,69 @@ impl OptionPair {
     fn has_long(&self) -> bool {
         !self.long.is_empty()
     }
+
+    fn to_pair(&self) -> (String, String) {
+        (self.short.to_string(), self.long.to_string())
+    }
+
+    fn from_names(pairs: &[(&str, &str)]) -> Vec<OptionPair> {
+        pairs
+            .iter()
+            .map(|(short, long)| OptionPair {
+                short: short.to_string(),
+                long: long.to_string(),
+            })
+            .collect()
+    }
+
+    /**
+     * Determine whether this refers to the registered option `info`.
+     */
+    fn names(&self, info: &OptionInfo) -> bool {
+        (self.has_short() && self.short == info.short)
+            || (self.has_long() && self.long == info.long)
+    }
+
+    /**
+     * Determine whether the option was provided, by either of its names.
+     */
+    fn present(&self, matches: &getopts::Matches) -> bool {
+        (self.has_short() && matches.opt_present(&self.short))
+            || (self.has_long() && matches.opt_present(&self.long))
+    }
+}
+
+/*
+ * The options that the user provided on the command line, and those options
+ * merged with values from the environment and the configuration file.
+ */
+type Parsed = (getopts::Matches, getopts::Matches);
+
+fn getopts_for(
+    options: &[OptionInfo],
+    filter: impl Fn(&OptionInfo) -> bool,
+) -> getopts::Options {
+    let mut out = getopts::Options::new();
+    out.parsing_style(getopts::ParsingStyle::StopAtFirstFree);
+    for info in options.iter().filter(|o| filter(o)) {
+        info.register(&mut out);
+    }
+    out
+}
+
+fn join_pairs(pairs: &[OptionPair], sep: &str) -> String {
+    pairs
+        .iter()
+        .map(|op| op.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
 }
 
 /**
  * Wrap an async level handler function in the appropriate pinned box for
- * registration with the `Level.cmd()` family of methods.
+ * registration with the `Level.cmd()` family of methods.  The function may
+ * also be passed directly, or a closure may be used instead; e.g., to bind
+ * extra arguments to a handler.
  */
 #[macro_export]
 macro_rules! cmd {
@@ -81,10 +359,7 @@ macro_rules! sel {
         match $level.select() {
             Ok(None) => return Ok(Default::default()),
             Ok(Some(sel)) => sel,
-            Err(e) => {
-                eprintln!("ERROR: {}", e);
-                std::process::exit(1);
-            }
+            Err(e) => return Err(e.into()),
         }
     };
 }
@@ -99,10 +374,7 @@ macro_rules! args {
         match $level.parse() {
             Ok(None) => return Ok(Default::default()),
             Ok(Some(mat)) => mat,
-            Err(e) => {
-                eprintln!("ERROR: {}", e);
-                std::process::exit(1);
-            }
+            Err(e) => return Err(e.into()),
         }
     };
 }
@@ -120,185 +392,873 @@ macro_rules! no_args {
             Ok(None) => return Ok(Default::default()),
             Ok(Some(args)) => {
                 if !args.opts().free.is_empty() {
-                    eprintln!("ERROR: unexpected arguments");
-                    std::process::exit(1);
+                    $crate::bad_args!($level, "unexpected arguments");
                 }
                 args
             }
-            Err(e) => {
-                eprintln!("ERROR: {}", e);
-                std::process::exit(1);
-            }
+            Err(e) => return Err(e.into()),
         }
     }};
 }
 
 /**
  * A macro like `bail!()` for reporting an issue with the provided arguments.
- * Presents usage information on stderr and then exits the program.
+ * Returns a `UsageError` from the current function, which will include usage
+ * information for the level when it is reported.
  */
 #[macro_export]
 macro_rules! bad_args {
     ($level:ident, $msg:literal) => {
-        $level.usage_error($msg);
-        std::process::exit(1);
+        return Err($level.usage_error($msg).into());
     };
     ($level:ident, $fmt:literal, $($arg:tt)*) => {
-        $level.usage_error(&format!($fmt, $($arg)*));
-        std::process::exit(1);
+        return Err($level.usage_error(&format!($fmt, $($arg)*)).into());
     };
 }
 
-pub struct Level<C: LevelContext> {
+/**
+ * An error in the arguments provided by the user, as reported by the
+ * `bad_args!()` macro or during argument parsing.  The error includes the
+ * usage information for the level at which it occurred, so that it can be
+ * presented to the user along with the message.
+ */
+#[derive(Debug)]
+pub struct UsageError {
     names: Vec<String>,
-    usage_args: Option<String>,
-    usage_opts: bool,
-    args: Option<Vec<String>>,
-    commands: Vec<CommandInfo<C>>,
-    options: getopts::Options,
-    options_required: Option<Vec<OptionPair>>,
-    options_mutex: Option<Vec<Vec<OptionPair>>>,
-    table: Option<table::TableBuilder>,
-    lazy_columns: bool,
-    private: C,
+    usage: String,
+    message: String,
 }
 
-impl<C: LevelContext> Level<C> {
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+impl UsageError {
     /**
-     * Create a new top-level command handling object.  The `name` is the
-     * project command name, and `private` is the consumer-provided context
-     * object to be passed to other level handlers.
+     * The names of each level, from the command name down to the level at
+     * which the error occurred.
      */
-    pub fn new(name: &str, private: C) -> Level<C> {
-        Level::new_sub(vec![name.to_string()], private, None)
+    pub fn names(&self) -> &[String] {
+        &self.names
     }
 
-    fn new_sub(
-        names: Vec<String>,
-        private: C,
-        args: Option<Vec<String>>,
-    ) -> Level<C> {
-        let mut options = getopts::Options::new();
-        options.parsing_style(getopts::ParsingStyle::StopAtFirstFree);
-        options.optflag("", "help", "usage information");
+    /**
+     * The usage information for the level at which the error occurred.
+     */
+    pub fn usage(&self) -> &str {
+        &self.usage
+    }
 
-        Level {
-            names,
-            usage_args: Some("[ARGS...]".to_string()),
-            usage_opts: false,
-            args,
-            commands: Vec::new(),
-            options,
-            options_required: None,
-            options_mutex: None,
-            table: None,
-            lazy_columns: false,
-            private,
-        }
+    pub fn message(&self) -> &str {
+        &self.message
     }
+}
 
-    /**
-     * Access the consumer-provided context object which is passed to all level
-     * handlers.
+/**
+ * The level in the command tree from which an error would be reported: the
+ * deepest level whose arguments have been parsed, or whose command has been
+ * dispatched, for the command that is running.
+ */
+#[derive(Clone, Default)]
+struct Position {
+    names: Vec<String>,
+    /*
+     * Whether the user asked for a backtrace with the option enabled by
+     * `Level::debug_errors()`.
      */
-    pub fn context(&self) -> &C {
-        &self.private
+    backtrace: bool,
+}
+
+/**
+ * Report the result of running a command on stderr, and determine the exit
+ * status for the process.  Usage errors are printed with usage information and
+ * result in an exit status of 2.  External commands report their own errors,
+ * so only their exit status is passed on.  Other errors are printed with the
+ * names of the command that failed and the chain of causes, and result in the
+ * exit status nominated with `ExitError`, or 1.
+ */
+fn report(res: Result<()>, pos: &Position, stderr: &mut dyn Write) -> u8 {
+    /*
+     * If we cannot write to stderr there is nobody to tell, so any errors
+     * here are ignored.
+     */
+    let e = match res {
+        Ok(()) => return 0,
+        Err(e) => e,
+    };
+
+    if let Some(ue) = e.downcast_ref::<UsageError>() {
+        write!(stderr, "{}", ue.usage()).ok();
+        writeln!(stderr, "ERROR: {}", ue.message()).ok();
+        return 2;
+    }
+    if let Some(code) =
+        e.downcast_ref::<PluginExit>().and_then(PluginExit::code)
+    {
+        return match code {
+            1..=255 => code as u8,
+            _ => 1,
+        };
     }
 
-    pub fn context_mut(&mut self) -> &mut C {
-        &mut self.private
+    if pos.names.is_empty() {
+        writeln!(stderr, "ERROR: {}", e).ok();
+    } else {
+        writeln!(stderr, "ERROR: {}: {}", pos.names.join(" "), e).ok();
+    }
+    for cause in e.chain().skip(1) {
+        writeln!(stderr, "    caused by: {}", cause).ok();
     }
 
-    pub fn discard_logger(&self) -> slog::Logger {
-        slog::Logger::root(slog::Discard, slog::o!())
+    let ee = e.downcast_ref::<ExitError>();
+    if pos.backtrace {
+        /*
+         * If a status was nominated for the error, the backtrace for the
+         * original error is closer to the source of the problem.
+         */
+        let bt = ee.map(|ee| ee.error().backtrace()).unwrap_or(e.backtrace());
+        if let BacktraceStatus::Captured = bt.status() {
+            writeln!(stderr, "backtrace:\n{}", bt).ok();
+        } else {
+            writeln!(
+                stderr,
+                "backtrace: not captured; set RUST_BACKTRACE=1 to enable"
+            )
+            .ok();
+        }
     }
 
-    /**
-     * Add a column to the table definition for this level.  The first time this
-     * is called for a level, table mode is activated.  Subsequent calls
-     * continue to add column definitions.
-     */
-    pub fn add_column(&mut self, name: &str, width: usize, default: bool) {
-        self.ensure_table().add_column(name, width, default);
+    ee.map(ExitError::code).unwrap_or(1)
+}
+
+async fn run_common<C: LevelContext>(level: Level<C>, func: Caller<C>) -> u8 {
+    let first = if let Some(args) = &level.args {
+        args.first().cloned()
+    } else {
+        std::env::args_os()
+            .nth(1)
+            .and_then(|a| a.into_string().ok())
+    };
+    if first.as_deref() == Some(complete::COMPLETE_COMMAND) {
+        return complete::complete(level, func).await;
     }
 
-    /**
-     * Normally we check column validity during option parsing.  If lazy
-     * validation is enabled, we won't validate the columns until producing
-     * table output.  This allows for tables that include a dynamic set of
-     * properties; e.g., when listing objects that each have their own
-     * dictionary of properties.
-     */
-    pub fn lazy_column_validation(&mut self, lazy: bool) {
-        self.ensure_table().lazy_columns(lazy);
+    let mut stderr = level.shared.stderr.clone();
+    let shared = level.shared.clone();
+    let names = level.names.clone();
+    let res = func(level).await;
+    report(res, &shared.take_position(names), &mut stderr)
+}
+
+/**
+ * Run the handler for the top-level command, and report any error that it
+ * returns.  The result is an exit status that may be returned from `main()`;
+ * e.g.,
+ *
+ * ```ignore
+ * #[tokio::main]
+ * async fn main() -> std::process::ExitCode {
+ *     hiercmd::run(Level::new("trial", ()), cmd!(do_trial)).await
+ * }
+ * ```
+ */
+pub async fn run<C, F, Fut>(level: Level<C>, func: F) -> ExitCode
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    ExitCode::from(run_common(level, caller(func)).await)
+}
+
+/**
+ * Report the result of running a command as per `run()`, and then exit the
+ * process with the appropriate status.  As only the result is available here,
+ * errors are reported without the names of the command that failed, and
+ * without a backtrace; `run()` reports both.
+ */
+pub fn exit_with(res: Result<()>) -> ! {
+    let pos = Position::default();
+    std::process::exit(report(res, &pos, &mut std::io::stderr()).into())
+}
+
+#[derive(Clone)]
+enum Sink {
+    Stdout,
+    Stderr,
+    Capture(Arc<Mutex<Vec<u8>>>),
+}
+
+/**
+ * A destination for output from a level handler.  Normally this is the
+ * process stdout or stderr, but output may also be captured in memory; e.g.,
+ * by the `testing` module.  Handlers should write their output here, rather
+ * than using `print!()`, so that it can be captured.
+ */
+#[derive(Clone)]
+pub struct Output {
+    sink: Sink,
+}
+
+impl Output {
+    fn capture() -> Output {
+        Output {
+            sink: Sink::Capture(Default::default()),
+        }
     }
 
-    fn ensure_table(&mut self) -> &mut table::TableBuilder {
-        if self.table.is_none() {
-            self.table = Some(table::TableBuilder::default());
+    fn is_captured(&self) -> bool {
+        matches!(self.sink, Sink::Capture(_))
+    }
 
-            /*
-             * Include the standard tabular data formatting options for this
-             * level.  They will be handled as part of printing the table after
-             * option parsing.
-             */
-            let o = &mut self.options;
-            o.optopt("s", "", "sort by column list (asc)", "COLUMNS");
-            o.optopt("S", "", "sort by column list (desc)", "COLUMNS");
-            o.optopt("o", "", "output column list", "COLUMNS");
-            //opts.optflag("a", "", "all fields");
-            o.optflag("H", "", "no header");
-            o.optflag("p", "", "print numbers in parseable (exact) format");
-            self.usage_opts = true;
+    fn captured(&self) -> String {
+        match &self.sink {
+            Sink::Capture(buf) => {
+                String::from_utf8_lossy(&buf.lock().unwrap()).to_string()
+            }
+            _ => String::new(),
         }
+    }
+}
 
-        self.table.as_mut().unwrap()
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &self.sink {
+            Sink::Stdout => std::io::stdout().write(buf),
+            Sink::Stderr => std::io::stderr().write(buf),
+            Sink::Capture(cap) => {
+                cap.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &self.sink {
+            Sink::Stdout => std::io::stdout().flush(),
+            Sink::Stderr => std::io::stderr().flush(),
+            Sink::Capture(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct GlobalOption {
+    info: OptionInfo,
+    count: usize,
+    values: Vec<String>,
+}
+
+/**
+ * The values of global options, as provided at the current level and every
+ * level above it.  Options are looked up by their short or long name, as with
+ * `getopts::Matches`.
+ */
+#[derive(Clone, Default)]
+pub struct Globals {
+    opts: Vec<GlobalOption>,
+}
+
+impl Globals {
+    fn find(&self, name: &str) -> Option<&GlobalOption> {
+        self.opts
+            .iter()
+            .find(|g| g.info.short == name || g.info.long == name)
     }
 
     /**
-     * Add a handler for a next level sub-command.  The `name` is what the user
-     * would pass on the command line to nominate the sub-command.  The `desc`
-     * is descriptive text that will show up in usage information.  The `func`
-     * callback is a function pointer for an asynchronous function wrapped by
-     * the `cmd!()` macro.
+     * Merge in the values of any global options from the parsed matches for
+     * a level.  Each global option is recorded even if it was not provided,
+     * so that levels below this one will know to accept it.
      */
-    pub fn cmd(
-        &mut self,
-        name: &str,
-        desc: &str,
-        func: Caller<C>,
-    ) -> Result<()> {
-        self.cmd_common(name, None, desc, func, true)
+    fn record(&mut self, options: &[OptionInfo], matches: &getopts::Matches) {
+        for info in options.iter().filter(|o| o.global) {
+            let pos = if let Some(pos) = self.opts.iter().position(|g| {
+                g.info.short == info.short && g.info.long == info.long
+            }) {
+                pos
+            } else {
+                self.opts.push(GlobalOption {
+                    info: info.clone(),
+                    count: 0,
+                    values: Vec::new(),
+                });
+                self.opts.len() - 1
+            };
+
+            let g = &mut self.opts[pos];
+            g.count += matches.opt_count(info.name());
+            g.values.extend(matches.opt_strs(info.name()));
+        }
+    }
+
+    pub fn opt_present(&self, name: &str) -> bool {
+        self.opt_count(name) > 0
     }
 
     /**
-     * Add a handler for a next level sub-command with a short alias.  Otherwise
-     * identical to the `cmd()` method.
+     * The number of times the option was provided, across all levels.
      */
-    pub fn cmda(
-        &mut self,
-        name: &str,
-        alias: &str,
-        desc: &str,
-        func: Caller<C>,
-    ) -> Result<()> {
-        self.cmd_common(name, Some(alias), desc, func, true)
+    pub fn opt_count(&self, name: &str) -> usize {
+        self.find(name).map(|g| g.count).unwrap_or(0)
     }
 
     /**
-     * Add a handler for a next level sub-command that is not shown in the usage
-     * output.  Otherwise identical to the `cmd()` method.
+     * The value of the option as provided at the lowest level, which takes
+     * precedence over any value provided above it.
      */
-    pub fn hcmd(
-        &mut self,
-        name: &str,
-        desc: &str,
-        func: Caller<C>,
-    ) -> Result<()> {
-        self.cmd_common(name, None, desc, func, false)
+    pub fn opt_str(&self, name: &str) -> Option<String> {
+        self.find(name).and_then(|g| g.values.last().cloned())
     }
 
-    fn cmd_common(
+    /**
+     * Every value provided for the option, from the top level down.
+     */
+    pub fn opt_strs(&self, name: &str) -> Vec<String> {
+        self.find(name)
+            .map(|g| g.values.clone())
+            .unwrap_or_default()
+    }
+}
+
+/**
+ * State that is passed down from each level to its sub-levels.
+ */
+#[derive(Clone)]
+struct Shared {
+    stdout: Output,
+    stderr: Output,
+    describe: Option<describe::Describer>,
+    globals: Globals,
+    config: Option<Arc<config::Config>>,
+    /*
+     * If the command is being run from the interactive shell, the place to
+     * which the context object is handed back once the command is done with
+     * it.
+     */
+    #[cfg(feature = "repl")]
+    interactive: Option<repl::Home>,
+    /*
+     * If the standard logging options are enabled, the logger constructed
+     * from them at the most recently parsed level.
+     */
+    logger: Option<slog::Logger>,
+    /*
+     * Hooks to run around each command, registered at this level and above.
+     */
+    hooks: Vec<hook::Hook>,
+    /*
+     * Environment variables to use in place of those of the process; e.g.,
+     * for tests.
+     */
+    env: Option<Arc<BTreeMap<String, OsString>>>,
+    /*
+     * Where in the tree the command being run has reached, shared by every
+     * level so that the position is known once the command returns.
+     */
+    position: Arc<Mutex<Option<Position>>>,
+    /*
+     * Whether the `--debug` option was enabled with `Level::debug_errors()`
+     * at this level or above.
+     */
+    debug_errors: bool,
+}
+
+impl Default for Shared {
+    fn default() -> Shared {
+        Shared {
+            stdout: Output { sink: Sink::Stdout },
+            stderr: Output { sink: Sink::Stderr },
+            describe: None,
+            globals: Globals::default(),
+            config: None,
+            #[cfg(feature = "repl")]
+            interactive: None,
+            logger: None,
+            hooks: Vec::new(),
+            env: None,
+            position: Arc::new(Mutex::new(None)),
+            debug_errors: false,
+        }
+    }
+}
+
+impl Shared {
+    /**
+     * The logger configured by the standard logging options, or one that
+     * discards log records if the options have not been enabled.
+     */
+    fn logger(&self) -> slog::Logger {
+        self.logger
+            .clone()
+            .unwrap_or_else(|| slog::Logger::root(slog::Discard, slog::o!()))
+    }
+
+    /**
+     * The position of the level with the provided `names`, as it stands once
+     * its arguments have been parsed.
+     */
+    fn position(&self, names: Vec<String>) -> Position {
+        Position {
+            names,
+            backtrace: self.debug_errors
+                && self.globals.opt_present(DEBUG_OPTION),
+        }
+    }
+
+    fn set_position(&self, pos: Option<Position>) -> Option<Position> {
+        std::mem::replace(&mut *self.position.lock().unwrap(), pos)
+    }
+
+    /**
+     * Take the position reached by the command that was run, for reporting
+     * its result.  If the command failed before any level was parsed, the
+     * position is that of the top level, with the provided `names`.
+     */
+    fn take_position(&self, names: Vec<String>) -> Position {
+        self.set_position(None).unwrap_or(Position {
+            names,
+            backtrace: false,
+        })
+    }
+}
+
+pub struct Level<C: LevelContext> {
+    names: Vec<String>,
+    usage_args: Option<String>,
+    usage_opts: bool,
+    args: Option<Vec<String>>,
+    commands: Vec<CommandInfo<C>>,
+    option_info: Vec<OptionInfo>,
+    options_required: Option<Vec<OptionPair>>,
+    options_mutex: Option<Vec<Vec<OptionPair>>>,
+    options_requires: Option<Vec<(OptionPair, OptionPair)>>,
+    options_any: Option<Vec<Vec<OptionPair>>>,
+    options_one: Option<Vec<Vec<OptionPair>>>,
+    positionals: Vec<Positional>,
+    table: Option<table::TableBuilder>,
+    lazy_columns: bool,
+    prefix_match: bool,
+    plugins: bool,
+    config_path: Option<PathBuf>,
+    /*
+     * The context object is only ever absent while describing the command
+     * tree below a command registered with `cmd_with()`.
+     */
+    private: Private<C>,
+    shared: Shared,
+}
+
+/**
+ * The context object held by a level or a selection.  When whatever holds it
+ * is dropped, the object is handed back: through the describer while
+ * describing the command tree, so that it can be passed to the next handler,
+ * or to the interactive shell, so that the same object is passed to the
+ * command on the next line.
+ */
+struct Private<C: LevelContext> {
+    value: Option<C>,
+    describe: Option<describe::Describer>,
+    #[cfg(feature = "repl")]
+    home: Option<repl::Home>,
+}
+
+impl<C: LevelContext> Private<C> {
+    fn new(value: Option<C>, shared: &Shared) -> Private<C> {
+        Private {
+            value,
+            describe: shared.describe.clone(),
+            #[cfg(feature = "repl")]
+            home: shared.interactive.clone(),
+        }
+    }
+
+    fn get(&self) -> &C {
+        self.value.as_ref().expect(NO_CONTEXT)
+    }
+
+    fn get_mut(&mut self) -> &mut C {
+        self.value.as_mut().expect(NO_CONTEXT)
+    }
+
+    fn take(&mut self) -> Option<C> {
+        self.value.take()
+    }
+
+    fn put(&mut self, value: C) {
+        self.value = Some(value);
+    }
+
+    /**
+     * Take the context object to pass on to the next level, which must not
+     * already have been taken.
+     */
+    fn into_inner(mut self) -> C {
+        self.value.take().unwrap()
+    }
+}
+
+impl<C: LevelContext> Drop for Private<C> {
+    fn drop(&mut self) {
+        let value = if let Some(value) = self.value.take() {
+            value
+        } else {
+            return;
+        };
+        if let Some(d) = &self.describe {
+            d.stash(value);
+        } else {
+            #[cfg(feature = "repl")]
+            if let Some(home) = &self.home {
+                home.stash(value);
+            }
+        }
+    }
+}
+
+impl<C: LevelContext> Level<C> {
+    /**
+     * Create a new top-level command handling object.  The `name` is the
+     * project command name, and `private` is the consumer-provided context
+     * object to be passed to other level handlers.
+     */
+    pub fn new(name: &str, private: C) -> Level<C> {
+        Level::new_sub(vec![name.to_string()], private, None, Shared::default())
+    }
+
+    /**
+     * Create a new top-level command handling object, as with `new()`, but
+     * using the provided arguments instead of those passed to the process.
+     * The arguments should not include the command name itself.
+     */
+    pub fn with_args(name: &str, private: C, args: Vec<String>) -> Level<C> {
+        Level::new_sub(
+            vec![name.to_string()],
+            private,
+            Some(args),
+            Shared::default(),
+        )
+    }
+
+    fn new_sub(
+        names: Vec<String>,
+        private: C,
+        args: Option<Vec<String>>,
+        shared: Shared,
+    ) -> Level<C> {
+        Level::new_level(names, Some(private), args, shared)
+    }
+
+    /**
+     * Create a level, which has no context object only while describing the
+     * command tree below a command registered with `cmd_with()`.
+     */
+    fn new_level(
+        names: Vec<String>,
+        private: Option<C>,
+        args: Option<Vec<String>>,
+        shared: Shared,
+    ) -> Level<C> {
+        let mut l = Level {
+            names,
+            usage_args: Some("[ARGS...]".to_string()),
+            usage_opts: false,
+            args,
+            commands: Vec::new(),
+            option_info: Vec::new(),
+            options_required: None,
+            options_mutex: None,
+            options_requires: None,
+            options_any: None,
+            options_one: None,
+            positionals: Vec::new(),
+            table: None,
+            lazy_columns: false,
+            prefix_match: false,
+            plugins: false,
+            config_path: None,
+            private: Private::new(private, &shared),
+            shared,
+        };
+        l.add_option("", "help", "usage information", "", false, false);
+
+        /*
+         * Accept any global options declared by the levels above this one.
+         */
+        for g in l.shared.globals.opts.clone() {
+            l.option_info.push(OptionInfo {
+                inherited: true,
+                ..g.info
+            });
+            l.usage_opts = true;
+        }
+        l
+    }
+
+    /**
+     * Access the consumer-provided context object which is passed to all level
+     * handlers.  While describing the command tree, there is no context object
+     * below a command registered with `cmd_with()`, and this panics.
+     */
+    pub fn context(&self) -> &C {
+        self.private.get()
+    }
+
+    pub fn context_mut(&mut self) -> &mut C {
+        self.private.get_mut()
+    }
+
+    /**
+     * Get a handle for writing regular output from this level.
+     */
+    pub fn stdout(&self) -> Output {
+        self.shared.stdout.clone()
+    }
+
+    /**
+     * Get a handle for writing diagnostic output from this level.
+     */
+    pub fn stderr(&self) -> Output {
+        self.shared.stderr.clone()
+    }
+
+    pub fn discard_logger(&self) -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /**
+     * Add the standard logging options to this level and every level below
+     * it: `-v` (which may be repeated) for more verbose output, `-q` for less,
+     * and `--log-format` to choose between human-readable and JSON output.
+     * Log records are written to the stderr of the level through the logger
+     * returned by `logger()`.
+     */
+    pub fn standard_logging(&mut self) {
+        self.global_optflagmulti(
+            "v",
+            logging::VERBOSE,
+            "more verbose log output (repeat for more)",
+        );
+        self.global_optflagmulti(
+            "q",
+            logging::QUIET,
+            "less verbose log output (repeat for less)",
+        );
+        let oi = self.add_option(
+            "",
+            logging::LOG_FORMAT,
+            "format for log output",
+            &logging::FORMATS.join("|"),
+            true,
+            false,
+        );
+        oi.global = true;
+        oi.choices = logging::FORMATS.iter().map(|f| f.to_string()).collect();
+        self.shared.logger =
+            Some(logging::logger(&self.shared.globals, self.stderr()));
+    }
+
+    /**
+     * The logger configured by the standard logging options, as provided at
+     * this level and above once the arguments have been parsed.  If the
+     * options have not been enabled with `standard_logging()`, log records
+     * are discarded.
+     */
+    pub fn logger(&self) -> slog::Logger {
+        self.shared.logger()
+    }
+
+    /**
+     * Add a global `--debug` option to this level and every level below it.
+     * When the option is provided, errors reported by `hiercmd::run()` are
+     * followed by a backtrace, if one was captured.
+     */
+    pub fn debug_errors(&mut self) {
+        self.shared.debug_errors = true;
+        self.global_optflag("", DEBUG_OPTION, "show a backtrace for errors");
+    }
+
+    /**
+     * Register a hook to run around each command selected at this level and
+     * every level below it.  The hook is passed a description of the command
+     * and the future that runs it.  The hook may await that future and then
+     * inspect or replace the result, or return without running the command at
+     * all.  Hooks registered first are outermost.  Hooks are not run while
+     * describing the command tree; e.g., for the built-in help command.
+     */
+    pub fn around_run<F, Fut>(&mut self, func: F)
+    where
+        F: Fn(Dispatch, HandlerFuture) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.shared
+            .hooks
+            .push(Arc::new(move |d, next| Box::pin(func(d, next))));
+    }
+
+    /**
+     * Register a hook to run before each command, as per `around_run()`.  If
+     * the hook fails, the command is not run and the error from the hook is
+     * returned instead.
+     */
+    pub fn before_run<F, Fut>(&mut self, func: F)
+    where
+        F: Fn(Dispatch) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.around_run(move |d, next| {
+            let before = func(d);
+            async move {
+                before.await?;
+                next.await
+            }
+        });
+    }
+
+    /**
+     * Register a hook to run after each command, as per `around_run()`.  The
+     * hook is passed the result of the command, and returns the result to
+     * report in its place.
+     */
+    pub fn after_run<F, Fut>(&mut self, func: F)
+    where
+        F: Fn(Dispatch, Result<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let func = Arc::new(func);
+        self.around_run(move |d, next| {
+            let func = Arc::clone(&func);
+            async move {
+                let res = next.await;
+                func(d, res).await
+            }
+        });
+    }
+
+    /**
+     * Add a column to the table definition for this level.  The first time this
+     * is called for a level, table mode is activated.  Subsequent calls
+     * continue to add column definitions.
+     */
+    pub fn add_column(&mut self, name: &str, width: usize, default: bool) {
+        self.ensure_table().add_column(name, width, default);
+    }
+
+    /**
+     * Normally we check column validity during option parsing.  If lazy
+     * validation is enabled, we won't validate the columns until producing
+     * table output.  This allows for tables that include a dynamic set of
+     * properties; e.g., when listing objects that each have their own
+     * dictionary of properties.
+     */
+    pub fn lazy_column_validation(&mut self, lazy: bool) {
+        self.ensure_table().lazy_columns(lazy);
+    }
+
+    fn ensure_table(&mut self) -> &mut table::TableBuilder {
+        if self.table.is_none() {
+            self.table = Some(table::TableBuilder::default());
+
+            /*
+             * Include the standard tabular data formatting options for this
+             * level.  They will be handled as part of printing the table after
+             * option parsing.
+             */
+            for (short, desc) in [
+                ("s", "sort by column list (asc)"),
+                ("S", "sort by column list (desc)"),
+                ("o", "output column list"),
+            ] {
+                self.add_option(short, "", desc, "COLUMNS", true, false)
+                    .columns = true;
+            }
+            //opts.optflag("a", "", "all fields");
+            self.add_option("H", "", "no header", "", false, false);
+            self.add_option(
+                "p",
+                "",
+                "print numbers in parseable (exact) format",
+                "",
+                false,
+                false,
+            );
+            self.usage_opts = true;
+        }
+
+        self.table.as_mut().unwrap()
+    }
+
+    /**
+     * Add a handler for a next level sub-command.  The `name` is what the user
+     * would pass on the command line to nominate the sub-command.  The `desc`
+     * is descriptive text that will show up in usage information.  The `func`
+     * callback is an asynchronous function, perhaps wrapped by the `cmd!()`
+     * macro, or a closure that returns a future; e.g.,
+     *
+     * ```ignore
+     * l.cmd("list", "list things", cmd!(do_list))?;
+     * l.cmd("boxes", "list boxes", |l| do_list_kind(l, Kind::Box))?;
+     * ```
+     *
+     * The same handler may be called more than once, so it must be `Fn`
+     * rather than `FnOnce`.
+     *
+     * To describe the command tree (e.g., for the built-in help command,
+     * manual pages, or completion) every handler is called without arguments,
+     * and runs until it parses its arguments with `sel!()` or `args!()`.  Any
+     * work a handler does before that point, such as connecting to a remote
+     * service, is done then too; such work belongs after the arguments have
+     * been parsed.  A handler that fails before then prevents the rest of the
+     * tree from being described, and the help command reports the error.
+     */
+    pub fn cmd<F, Fut>(&mut self, name: &str, desc: &str, func: F) -> Result<()>
+    where
+        F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.cmd_common(name, None, desc, caller(func), true)
+    }
+
+    /**
+     * Add a handler for a next level sub-command with a short alias.  Otherwise
+     * identical to the `cmd()` method.
+     */
+    pub fn cmda<F, Fut>(
+        &mut self,
+        name: &str,
+        alias: &str,
+        desc: &str,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.cmd_common(name, Some(alias), desc, caller(func), true)
+    }
+
+    /**
+     * Add a handler for a next level sub-command that is not shown in the usage
+     * output.  Otherwise identical to the `cmd()` method.
+     */
+    pub fn hcmd<F, Fut>(
+        &mut self,
+        name: &str,
+        desc: &str,
+        func: F,
+    ) -> Result<()>
+    where
+        F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.cmd_common(name, None, desc, caller(func), false)
+    }
+
+    fn cmd_common(
         &mut self,
         name: &str,
         alias: Option<&str>,
@@ -306,369 +1266,3129 @@ impl<C: LevelContext> Level<C> {
         func: Caller<C>,
         visible: bool,
     ) -> Result<()> {
-        if self.commands.iter().any(|ci| ci.name == name) {
-            bail!("duplicate command \"{}\"", name);
+        self.check_name(name)?;
+        if let Some(alias) = alias {
+            if alias == name {
+                bail!("alias \"{}\" is the same as the command name", alias);
+            }
+            self.check_name(alias)?;
         }
         self.commands.push(CommandInfo {
             name: name.to_string(),
-            alias: alias.map(|s| s.to_string()),
+            aliases: alias.iter().map(|s| s.to_string()).collect(),
+            hidden_aliases: Vec::new(),
             desc: desc.to_string(),
             func,
             visible,
+            default: false,
+            mapped: false,
         });
         Ok(())
     }
 
     /**
-     * Provide a description of the arguments this level accepts for inclusion
-     * in the usage message.  By default, `"[ARGS...]"` is shown.
+     * Add a handler for a next level sub-command that uses a different type
+     * of context object, of type `D`.  The level handler must run the
+     * selection for this command with `Selection::run_with()` (or
+     * `run_with_async()`), passing the returned `Mapped` along with a
+     * function to produce the context object for the new level from the
+     * current one; e.g.,
+     *
+     * ```ignore
+     * let nodes = l.cmd_with("nodes", "manage nodes", do_nodes)?;
+     * let s = sel!(l);
+     * s.run_with(&nodes, |c| Client::new(c)).await
+     * ```
+     *
+     * Otherwise identical to the `cmd()` method.  While describing the
+     * command tree, the handler for the new level is run without a context
+     * object, as producing one may mean connecting to a remote service, so
+     * it must not use `Level::context()` before it has parsed its arguments.
      */
-    pub fn usage_args(&mut self, snippet: Option<&str>) {
-        self.usage_args = snippet.map(|s| s.to_string());
-    }
-
-    pub fn optflagmulti(
+    pub fn cmd_with<D, F, Fut>(
         &mut self,
-        short_name: &str,
-        long_name: &str,
+        name: &str,
         desc: &str,
-    ) {
-        self.usage_opts = true;
-        self.options.optflagmulti(short_name, long_name, desc);
-    }
+        func: F,
+    ) -> Result<Mapped<D>>
+    where
+        D: LevelContext,
+        F: Fn(Level<D>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let func: Caller<D> = caller(func);
 
-    pub fn optmulti(
-        &mut self,
-        short_name: &str,
-        long_name: &str,
-        desc: &str,
+        /*
+         * The handler for the current context type is only used while
+         * describing the tree, where it describes the new level without a
+         * context object.  The context object for this level is handed back
+         * when the level is dropped, as usual.
+         */
+        let describe = Arc::clone(&func);
+        let placeholder = move |l: Level<C>| {
+            let describe = Arc::clone(&describe);
+            async move {
+                let d = if let Some(d) = l.shared.describe.clone() {
+                    d
+                } else {
+                    bail!(
+                        "command \"{}\" must be run with \
+                        Selection::run_with()",
+                        l.names.last().unwrap()
+                    );
+                };
+
+                let names = l.names.clone();
+                let sub = Level::new_level(
+                    l.names.clone(),
+                    None,
+                    l.args.clone(),
+                    l.shared.clone(),
+                );
+                d.complete(&names, describe(sub).await)
+            }
+        };
+        self.cmd_common(name, None, desc, caller(placeholder), true)?;
+        self.commands.last_mut().unwrap().mapped = true;
+
+        let mut names = self.names.clone();
+        names.push(name.to_string());
+        Ok(Mapped { names, func })
+    }
+
+    /**
+     * Add an alias for a sub-command that has already been registered with
+     * the `cmd()` family of methods.  Commands may have any number of aliases,
+     * and each will be shown in the usage output.
+     */
+    pub fn alias(&mut self, name: &str, alias: &str) -> Result<()> {
+        self.alias_common(name, alias, true)
+    }
+
+    /**
+     * Add an alias for a sub-command that is not shown in the usage output;
+     * e.g., to preserve an old name for backwards compatibility.  Otherwise
+     * identical to the `alias()` method.
+     */
+    pub fn halias(&mut self, name: &str, alias: &str) -> Result<()> {
+        self.alias_common(name, alias, false)
+    }
+
+    fn alias_common(
+        &mut self,
+        name: &str,
+        alias: &str,
+        visible: bool,
+    ) -> Result<()> {
+        self.check_name(alias)?;
+        let ci = if let Some(ci) =
+            self.commands.iter_mut().find(|ci| ci.name == name)
+        {
+            ci
+        } else {
+            bail!("cannot alias unknown command \"{}\"", name);
+        };
+        if visible {
+            ci.aliases.push(alias.to_string());
+        } else {
+            ci.hidden_aliases.push(alias.to_string());
+        }
+        Ok(())
+    }
+
+    /**
+     * Run the sub-command `name`, which must already have been registered
+     * with the `cmd()` family of methods, when the user does not nominate a
+     * command at this level; e.g., so that "trial thing" is the same as
+     * "trial thing list".  Any arguments that do not begin with a command are
+     * passed to the default, so "trial thing -o name" is the same as "trial
+     * thing list -o name".  Only one command may be the default.
+     */
+    pub fn default_cmd(&mut self, name: &str) -> Result<()> {
+        if let Some(ci) = self.commands.iter().find(|ci| ci.default) {
+            bail!("command \"{}\" is already the default", ci.name);
+        }
+        if let Some(ci) = self.commands.iter_mut().find(|ci| ci.name == name) {
+            ci.default = true;
+            Ok(())
+        } else {
+            bail!("cannot make unknown command \"{}\" the default", name);
+        }
+    }
+
+    /**
+     * Ensure that a new command name or alias does not collide with the name
+     * or any alias of a command already registered at this level.
+     */
+    fn check_name(&self, name: &str) -> Result<()> {
+        for ci in self.commands.iter() {
+            if ci.name == name {
+                bail!("duplicate command \"{}\"", name);
+            }
+            if ci.matches(name) {
+                bail!(
+                    "\"{}\" is already an alias for command \"{}\"",
+                    name,
+                    ci.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Allow the user to nominate a sub-command at this level with any prefix
+     * of its name that is unique amongst the visible commands; e.g., "li" for
+     * "list".  Exact matches on names and aliases are always preferred.
+     */
+    pub fn prefix_match(&mut self, enable: bool) {
+        self.prefix_match = enable;
+    }
+
+    /**
+     * Look for a command that is not registered at this level as an external
+     * executable on the user's `PATH`, named for each level and the command;
+     * e.g., "trial-thing-frob" for "trial thing frob".  The executable is run
+     * with the remaining arguments, and its exit status is passed on through
+     * a `PluginExit` error if it fails, without blocking the thread of the
+     * async executor while it runs.  Available external commands are listed
+     * in the usage output; as that means reading each directory on `PATH`,
+     * it is only done when the usage is shown for help or an error.
+     */
+    pub fn plugin_commands(&mut self, enable: bool) {
+        self.plugins = enable;
+    }
+
+    /**
+     * Locate an external command, if they are enabled for this level.
+     */
+    fn find_plugin(&self, want: &str) -> Option<PathBuf> {
+        let search = self.plugin_path();
+        plugin::find(search.as_deref(), &self.names.join("-"), want)
+    }
+
+    /**
+     * The search path for external commands, if they are enabled for this
+     * level.
+     */
+    fn plugin_path(&self) -> Option<OsString> {
+        if !self.plugins {
+            return None;
+        }
+        self.env_var("PATH")
+    }
+
+    /**
+     * Load default option values for this level, and every level below it,
+     * from the configuration file at `path`.  Values provided on the command
+     * line or through the environment take precedence over those in the
+     * file.  A file that does not exist is treated as if it were empty, and
+     * the user can ignore the file with the `--no-config` flag at any level.
+     * If this is called more than once, the last path is used.
+     */
+    pub fn config_file(&mut self, path: &Path) {
+        self.config_path = Some(path.to_path_buf());
+        if !self.option_info.iter().any(|o| o.long == NO_CONFIG) {
+            self.global_optflag("", NO_CONFIG, "ignore the configuration file");
+        }
+    }
+
+    /**
+     * Provide a description of the arguments this level accepts for inclusion
+     * in the usage message.  By default, `"[ARGS...]"` is shown.
+     */
+    pub fn usage_args(&mut self, snippet: Option<&str>) {
+        self.usage_args = snippet.map(|s| s.to_string());
+    }
+
+    /**
+     * Declare a positional argument for a terminal level.  Once any have been
+     * declared, the arguments provided by the user are checked against them
+     * during argument parsing, the usage synopsis is generated from them, and
+     * the values are available by name through `Arguments::positional()`.
+     *
+     * Required arguments must be declared before optional ones, and a
+     * variadic argument must be declared last; otherwise, an error is
+     * returned.  A variadic argument must not have a `min` greater than its
+     * `max`.
+     */
+    pub fn positional(
+        &mut self,
+        name: &str,
+        desc: &str,
+        arity: Arity,
+    ) -> Result<&mut Positional> {
+        if let Arity::Variadic {
+            min,
+            max: Some(max),
+        } = arity
+        {
+            if min > max {
+                bail!(
+                    "variadic argument {} has a minimum ({}) greater than \
+                    its maximum ({})",
+                    name,
+                    min,
+                    max
+                );
+            }
+        }
+        if let Some(last) = self.positionals.last() {
+            if matches!(last.arity, Arity::Variadic { .. }) {
+                bail!(
+                    "positional argument {} follows variadic argument {}",
+                    name,
+                    last.name
+                );
+            }
+            if arity == Arity::Required && last.arity != Arity::Required {
+                bail!(
+                    "required positional argument {} follows optional \
+                    argument {}",
+                    name,
+                    last.name
+                );
+            }
+        }
+        self.positionals.push(Positional::new(name, desc, arity));
+        Ok(self.positionals.last_mut().unwrap())
+    }
+
+    fn add_option(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+        hint: &str,
+        hasarg: bool,
+        multi: bool,
+    ) -> &mut OptionInfo {
+        self.option_info.push(OptionInfo {
+            short: short_name.to_string(),
+            long: long_name.to_string(),
+            desc: desc.to_string(),
+            hint: hint.to_string(),
+            hasarg,
+            multi,
+            required: false,
+            columns: false,
+            global: false,
+            inherited: false,
+            env: None,
+            choices: Vec::new(),
+        });
+        self.option_info.last_mut().unwrap()
+    }
+
+    pub fn optflagmulti(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+    ) {
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, "", false, true);
+    }
+
+    pub fn optmulti(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
         hint: &str,
     ) {
         self.usage_opts = true;
-        self.options.optmulti(short_name, long_name, desc, hint);
+        self.add_option(short_name, long_name, desc, hint, true, true);
     }
 
     pub fn optflag(&mut self, short_name: &str, long_name: &str, desc: &str) {
         self.usage_opts = true;
-        self.options.optflag(short_name, long_name, desc);
+        self.add_option(short_name, long_name, desc, "", false, false);
+    }
+
+    pub fn optopt(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+        hint: &str,
+    ) {
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, hint, true, false);
+    }
+
+    pub fn reqopt(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+        hint: &str,
+    ) {
+        if self.options_required.is_none() {
+            self.options_required = Some(Vec::new());
+        }
+        self.options_required.as_mut().unwrap().push(OptionPair {
+            short: short_name.to_string(),
+            long: long_name.to_string(),
+        });
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, hint, true, false)
+            .required = true;
+    }
+
+    /**
+     * Add an option that accepts only one of a fixed set of values; e.g.,
+     * `--state running|stopped`.  The choices are shown in the usage output,
+     * and any other value is rejected during argument parsing.
+     */
+    pub fn optchoice(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+        choices: &[&str],
+    ) {
+        self.usage_opts = true;
+        let hint = choices.join("|");
+        self.add_option(short_name, long_name, desc, &hint, true, false)
+            .choices = choices.iter().map(|c| c.to_string()).collect();
+    }
+
+    /**
+     * Add a flag that is accepted at this level and at every level below it,
+     * in any position.  The presence of global options may be checked from
+     * any descendant level through `Arguments::globals()`.
+     */
+    pub fn global_optflag(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+    ) {
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, "", false, false)
+            .global = true;
+    }
+
+    /**
+     * Add a global flag that may be repeated.  Otherwise identical to the
+     * `global_optflag()` method.
+     */
+    pub fn global_optflagmulti(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+    ) {
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, "", false, true)
+            .global = true;
+    }
+
+    /**
+     * Add a global option that takes a value.  If the option is provided at
+     * more than one level, the value from the lowest level is used.
+     * Otherwise identical to the `global_optflag()` method.
+     */
+    pub fn global_optopt(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+        hint: &str,
+    ) {
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, hint, true, false)
+            .global = true;
+    }
+
+    /**
+     * Add a global option that takes a value and may be repeated.  Otherwise
+     * identical to the `global_optflag()` method.
+     */
+    pub fn global_optmulti(
+        &mut self,
+        short_name: &str,
+        long_name: &str,
+        desc: &str,
+        hint: &str,
+    ) {
+        self.usage_opts = true;
+        self.add_option(short_name, long_name, desc, hint, true, true)
+            .global = true;
+    }
+
+    /**
+     * Take the value of an option that was registered with `optopt()` or
+     * `reqopt()` from the nominated environment variable, if the option is
+     * not provided on the command line.  A required option is considered to
+     * be present if the variable is set to a value that is not empty.  A
+     * value from the environment is not used if the option is mutually
+     * exclusive with one that the user provided.
+     */
+    pub fn option_env(&mut self, name: &str, var: &str) -> Result<()> {
+        let oi =
+            if let Some(oi) = self.option_info.iter_mut().find(|oi| {
+                !oi.inherited && (oi.short == name || oi.long == name)
+            }) {
+                oi
+            } else {
+                bail!("cannot set environment for unknown option \"{}\"", name);
+            };
+        if !oi.hasarg {
+            bail!("option \"{}\" does not take a value", name);
+        }
+        oi.env = Some(var.to_string());
+        Ok(())
+    }
+
+    pub fn mutually_exclusive(&mut self, pairs: &[(&str, &str)]) {
+        if self.options_mutex.is_none() {
+            self.options_mutex = Some(Vec::new());
+        }
+        self.options_mutex
+            .as_mut()
+            .unwrap()
+            .push(OptionPair::from_names(pairs));
+    }
+
+    /**
+     * Require that if the option `opt` is provided, the option `needs` must
+     * be provided as well; e.g., `--key` requires `--cert`.
+     */
+    pub fn requires(&mut self, opt: (&str, &str), needs: (&str, &str)) {
+        if self.options_requires.is_none() {
+            self.options_requires = Some(Vec::new());
+        }
+        let mut pairs = OptionPair::from_names(&[opt, needs]);
+        let needs = pairs.pop().unwrap();
+        let opt = pairs.pop().unwrap();
+        self.options_requires.as_mut().unwrap().push((opt, needs));
+    }
+
+    /**
+     * Require that at least one of a group of options is provided.
+     */
+    pub fn at_least_one_of(&mut self, pairs: &[(&str, &str)]) {
+        if self.options_any.is_none() {
+            self.options_any = Some(Vec::new());
+        }
+        self.options_any
+            .as_mut()
+            .unwrap()
+            .push(OptionPair::from_names(pairs));
+    }
+
+    /**
+     * Require that exactly one of a group of options is provided.
+     */
+    pub fn exactly_one_of(&mut self, pairs: &[(&str, &str)]) {
+        if self.options_one.is_none() {
+            self.options_one = Some(Vec::new());
+        }
+        self.options_one
+            .as_mut()
+            .unwrap()
+            .push(OptionPair::from_names(pairs));
+    }
+
+    /**
+     * If this command level is a terminal node, just parse arguments and the
+     * optional table.  This should be called via the `args()!` macro, or if the
+     * command does not expect any arguments, the `no_args()!` macro.
+     * Automatically handles `--help` and any table output formatting options.
+     */
+    pub fn parse(&mut self) -> Result<Option<Arguments>> {
+        if let Some(d) = &self.shared.describe {
+            /*
+             * When describing the command tree, we record what we know about
+             * this level rather than validating the arguments or running the
+             * rest of the handler.
+             */
+            d.record(self.describe(self.parse_matches().ok()));
+            return Ok(None);
+        }
+
+        self.prepare_config()?;
+
+        match self.parse_all() {
+            Ok((typed, res)) => {
+                if typed.opt_present("help") {
+                    self.usage();
+                    return Ok(None);
+                }
+
+                self.shared.globals.record(&self.option_info, &res);
+                self.shared.set_position(Some(
+                    self.shared.position(self.names.clone()),
+                ));
+                if self.shared.logger.is_some() {
+                    self.shared.logger = Some(logging::logger(
+                        &self.shared.globals,
+                        self.stderr(),
+                    ));
+                }
+
+                /*
+                 * Ensure all required options are present.
+                 */
+                if let Some(reqopts) = &self.options_required {
+                    let mut missing = Vec::new();
+                    for op in reqopts.iter() {
+                        if !op.present(&res) {
+                            missing.push(op.to_string());
+                        }
+                    }
+
+                    if !missing.is_empty() {
+                        bad_args!(
+                            self,
+                            "required options missing: {}",
+                            missing.join(", ")
+                        );
+                    }
+                }
+
+                /*
+                 * Ensure there are no conflicts between mutually exclusive
+                 * options.  Values from the environment or the configuration
+                 * file are not considered, as the user did not provide them.
+                 */
+                if let Some(mutopts) = &self.options_mutex {
+                    for opts in mutopts.iter() {
+                        let conflicts = opts
+                            .iter()
+                            .filter(|opt| opt.present(&typed))
+                            .map(|s| s.to_string())
+                            .collect::<Vec<_>>();
+                        if conflicts.len() > 1 {
+                            bad_args!(
+                                self,
+                                "{} are mutually exclusive",
+                                conflicts.join(" and "),
+                            );
+                        }
+                    }
+                }
+
+                /*
+                 * Ensure that options which depend on other options are
+                 * accompanied by them.
+                 */
+                for (opt, needs) in self.options_requires.iter().flatten() {
+                    if opt.present(&res) && !needs.present(&res) {
+                        bad_args!(self, "{} requires {}", opt, needs);
+                    }
+                }
+
+                /*
+                 * Ensure that at least one option, or exactly one option, is
+                 * present from each group that requires it.  Values from the
+                 * environment or the configuration file count here, unless
+                 * they were set aside for one the user provided.
+                 */
+                for opts in self.options_any.iter().flatten() {
+                    if !opts.iter().any(|opt| opt.present(&res)) {
+                        bad_args!(
+                            self,
+                            "at least one of {} is required",
+                            join_pairs(opts, ", "),
+                        );
+                    }
+                }
+                for opts in self.options_one.iter().flatten() {
+                    if !opts.iter().any(|opt| opt.present(&res)) {
+                        bad_args!(
+                            self,
+                            "exactly one of {} is required",
+                            join_pairs(opts, ", "),
+                        );
+                    }
+                    let found = opts
+                        .iter()
+                        .filter(|opt| opt.present(&res))
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>();
+                    if found.len() > 1 {
+                        bad_args!(
+                            self,
+                            "{} are mutually exclusive",
+                            found.join(" and "),
+                        );
+                    }
+                }
+
+                /*
+                 * Ensure that options with a fixed set of values were given
+                 * one of them.
+                 */
+                for oi in self.option_info.iter() {
+                    if oi.choices.is_empty() {
+                        continue;
+                    }
+                    for val in res.opt_strs(oi.name()) {
+                        if !oi.choices.contains(&val) {
+                            let dym =
+                                suggest::did_you_mean(&val, oi.choices.iter());
+                            bad_args!(
+                                self,
+                                "invalid value \"{}\" for option {}: must be \
+                                one of {}{}",
+                                val,
+                                oi.pair(),
+                                oi.choices.join(", "),
+                                dym,
+                            );
+                        }
+                    }
+                }
+
+                /*
+                 * Declared positional arguments only apply at a terminal
+                 * level, as otherwise the first argument is the command.
+                 */
+                let mut positionals = Vec::new();
+                if !self.positionals.is_empty() && self.commands.is_empty() {
+                    match positional::assign(&self.positionals, &res.free) {
+                        Ok(vals) => positionals = vals,
+                        Err(msg) => {
+                            bad_args!(self, "{}", msg);
+                        }
+                    }
+                }
+
+                if let Some(table) = self.table.as_mut() {
+                    table
+                        .output_from_list(res.opt_str("o").as_deref())
+                        .sort_from_list_asc(res.opt_str("s").as_deref())
+                        .sort_from_list_desc(res.opt_str("S").as_deref())
+                        .show_header(!res.opt_present("H"))
+                        .tab_separated(res.opt_present("H"))
+                        .parseable(res.opt_present("p"));
+                }
+
+                /*
+                 * Check the column names while the table is still part of
+                 * this level, so that the usage message includes the list of
+                 * valid columns.
+                 */
+                if !self.lazy_columns {
+                    if let Some(msg) = self
+                        .table
+                        .as_ref()
+                        .and_then(|t| t.missing_column_error())
+                    {
+                        bad_args!(self, "{}", msg);
+                    }
+                }
+
+                Ok(Some(Arguments {
+                    usage: self.usage_info(),
+                    positionals,
+                    matches: res,
+                    table: self.table.take(),
+                    globals: self.shared.globals.clone(),
+                }))
+            }
+            Err(getopts::Fail::UnrecognizedOption(nm)) if nm.len() > 1 => {
+                let dym = suggest::did_you_mean(
+                    &format!("--{}", nm),
+                    self.option_info
+                        .iter()
+                        .filter(|o| !o.long.is_empty())
+                        .map(|o| format!("--{}", o.long)),
+                );
+                bad_args!(self, "Unrecognized option: '{}'{}", nm, dym);
+            }
+            Err(e) => {
+                bad_args!(self, "{}", e);
+            }
+        }
+    }
+
+    fn parse_matches(
+        &self,
+    ) -> std::result::Result<getopts::Matches, getopts::Fail> {
+        self.parse_all().map(|(_, matches)| matches)
+    }
+
+    /**
+     * Parse the arguments for this level, producing both the options that
+     * the user provided on the command line and those options merged with
+     * values from the environment and the configuration file.
+     */
+    fn parse_all(&self) -> std::result::Result<Parsed, getopts::Fail> {
+        let options = self.getopts(|_| true);
+        let args = self.raw_args();
+        let typed = options.parse(&args)?;
+
+        /*
+         * Options that were not provided on the command line may have a value
+         * in the environment.  Those values are put ahead of the arguments so
+         * that they are parsed in the same way as if the user had provided
+         * them.  Inherited options are left to the level that declared them,
+         * and an option that is mutually exclusive with one the user provided
+         * is left out, as the user has chosen the other.  An empty variable
+         * is treated as if it were not set.
+         */
+        let mut extra: Vec<OsString> = Vec::new();
+        for info in self.option_info.iter().filter(|o| !o.inherited) {
+            if typed.opt_present(info.name()) || self.excluded(info, &typed) {
+                continue;
+            }
+            if let Some(val) = info
+                .env
+                .as_deref()
+                .and_then(|var| self.env_var(var))
+                .filter(|val| !val.is_empty())
+            {
+                extra.push(info.flag().into());
+                extra.push(val);
+            } else if let Some(value) = self.config_value(info) {
+                /*
+                 * Values from the configuration file were checked by
+                 * check_config() before we got here.
+                 */
+                let words = info.config_args(value).unwrap_or_default();
+                extra.extend(words.into_iter().map(OsString::from));
+            }
+        }
+        if extra.is_empty() {
+            return Ok((typed.clone(), typed));
+        }
+        extra.extend(args);
+        let merged = options.parse(&extra)?;
+        Ok((typed, merged))
+    }
+
+    /**
+     * Determine whether an option is mutually exclusive with another option
+     * that the user provided.
+     */
+    fn excluded(&self, info: &OptionInfo, typed: &getopts::Matches) -> bool {
+        self.options_mutex
+            .iter()
+            .flatten()
+            .chain(self.options_one.iter().flatten())
+            .filter(|opts| opts.iter().any(|op| op.names(info)))
+            .any(|opts| {
+                opts.iter().any(|op| !op.names(info) && op.present(typed))
+            })
+    }
+
+    /**
+     * Look up an environment variable for an option value.
+     */
+    fn env_var(&self, var: &str) -> Option<OsString> {
+        if let Some(env) = &self.shared.env {
+            env.get(var).cloned()
+        } else {
+            std::env::var_os(var)
+        }
+    }
+
+    fn raw_args(&self) -> Vec<OsString> {
+        if let Some(args) = &self.args {
+            args.iter().map(OsString::from).collect()
+        } else {
+            std::env::args_os().skip(1).collect()
+        }
+    }
+
+    /**
+     * The arguments for this level as strings, so that they can be rewritten
+     * before parsing.  An argument that is not valid UTF-8 is reported as an
+     * error, rather than being altered on the way through.
+     */
+    fn string_args(&self) -> Result<Vec<String>> {
+        if let Some(args) = &self.args {
+            return Ok(args.clone());
+        }
+        std::env::args_os()
+            .skip(1)
+            .map(|a| {
+                a.into_string().map_err(|a| {
+                    anyhow::anyhow!("argument {:?} is not valid UTF-8", a)
+                })
+            })
+            .collect()
+    }
+
+    /**
+     * Determine whether the user provided the flag `long`, either at this
+     * level or at any level below it, without regard to the environment or
+     * the configuration file.  As option parsing stops at the first free
+     * argument, any flags for the levels below are among the free arguments.
+     */
+    fn requested(&self, long: &str) -> bool {
+        if !self.option_info.iter().any(|o| o.long == long) {
+            return false;
+        }
+        let flag = format!("--{}", long);
+        match self.getopts(|_| true).parse(self.raw_args()) {
+            Ok(m) => {
+                m.opt_present(long)
+                    || m.free
+                        .iter()
+                        .take_while(|w| *w != "--")
+                        .any(|w| *w == flag)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /**
+     * Load and check the configuration file for this level.  If the user asked
+     * for help, a problem with the file should not prevent them from getting
+     * it, so the file is ignored instead.
+     */
+    fn prepare_config(&mut self) -> Result<()> {
+        let res = match self.load_config() {
+            Ok(()) => self.check_config(),
+            Err(e) => Err(e),
+        };
+        match res {
+            Err(_) if self.requested("help") => {
+                self.shared.config = None;
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /**
+     * Load the configuration file nominated for this level, if there is one
+     * and it has not been loaded already.  If the user asked for the file to
+     * be ignored, at this level or any other, it is not used here or below.
+     */
+    fn load_config(&mut self) -> Result<()> {
+        if self.shared.globals.opt_present(NO_CONFIG)
+            || self.requested(NO_CONFIG)
+        {
+            self.config_path = None;
+            self.shared.config = None;
+        } else if let Some(path) = self.config_path.take() {
+            /*
+             * A top-level command named "alias" has its options in the
+             * "alias" table, so that table cannot also hold command aliases.
+             */
+            let aliases = match self.names.get(1) {
+                Some(name) => name != config::ALIAS_TABLE,
+                None => !self
+                    .commands
+                    .iter()
+                    .any(|ci| ci.name == config::ALIAS_TABLE),
+            };
+            self.shared.config =
+                Some(Arc::new(config::Config::load(&path, aliases)?));
+        }
+        Ok(())
+    }
+
+    /**
+     * The command aliases from the configuration file for this level, if
+     * any.
+     */
+    fn aliases(&self) -> &[(String, Vec<String>)] {
+        self.shared
+            .config
+            .as_ref()
+            .map(|c| c.aliases(&self.names[1..]))
+            .unwrap_or_default()
+    }
+
+    /**
+     * If the user nominated a command alias from the configuration file,
+     * replace it with the words it expands to.  As the expansion may itself
+     * begin with an alias, this is repeated until the command is not an
+     * alias.
+     */
+    fn expand_aliases(&mut self) -> Result<()> {
+        let config = if let Some(config) = self.shared.config.clone() {
+            config
+        } else {
+            return Ok(());
+        };
+        let aliases = config.aliases(&self.names[1..]);
+
+        let mut seen: Vec<&str> = Vec::new();
+        loop {
+            /*
+             * If the arguments cannot be parsed, the failure will be
+             * reported when we parse them for real.
+             */
+            let free = match self.parse_matches() {
+                Ok(m) => m.free,
+                Err(_) => return Ok(()),
+            };
+            let (name, words) = if let Some((name, words)) = free
+                .first()
+                .and_then(|want| aliases.iter().find(|(n, _)| n == want))
+            {
+                (name.as_str(), words)
+            } else {
+                return Ok(());
+            };
+
+            /*
+             * Registered commands always take precedence, so an alias with the
+             * same name as one could never be used.  This is only reported
+             * when the user types that name, so that one bad alias does not
+             * break help or any other command.
+             */
+            let builtin = self.builtin_help() && name == HELP_COMMAND;
+            if builtin || self.commands.iter().any(|ci| ci.matches(name)) {
+                if !seen.is_empty() || self.requested("help") {
+                    return Ok(());
+                }
+                bail!(
+                    "config file {}: alias \"{}\" conflicts with a command \
+                    of the same name",
+                    config.path().display(),
+                    name
+                );
+            }
+
+            if seen.contains(&name) {
+                seen.push(name);
+                bail!("alias \"{}\" is recursive: {}", name, seen.join(" -> "));
+            }
+            seen.push(name);
+
+            /*
+             * The positional arguments are always at the end of the argument
+             * list, as option parsing stops at the first of them.
+             */
+            let mut args = self.string_args()?;
+            let pos = args.len() - free.len();
+            args.splice(pos..=pos, words.iter().cloned());
+            self.args = Some(args);
+        }
+    }
+
+    /**
+     * Locate the value for an option in the configuration file, if any.
+     */
+    fn config_value(&self, info: &OptionInfo) -> Option<&config::Value> {
+        self.shared
+            .config
+            .as_ref()?
+            .table(&self.names[1..])
+            .iter()
+            .find(|(k, _)| {
+                (!info.short.is_empty() && k == &info.short)
+                    || (!info.long.is_empty() && k == &info.long)
+            })
+            .map(|(_, v)| v)
+    }
+
+    /**
+     * Ensure that each key in the configuration file for this level is an
+     * option that can accept the value provided.
+     */
+    fn check_config(&self) -> Result<()> {
+        let config = if let Some(config) = &self.shared.config {
+            config
+        } else {
+            return Ok(());
+        };
+
+        for (key, value) in config.table(&self.names[1..]) {
+            let info = self
+                .option_info
+                .iter()
+                .find(|o| !o.inherited && (o.short == *key || o.long == *key));
+            let res = if let Some(info) = info {
+                info.config_args(value).map(|_| ())
+            } else {
+                Err(anyhow::anyhow!(
+                    "unknown option \"{}\" for \"{}\"",
+                    key,
+                    self.names.join(" ")
+                ))
+            };
+            if let Err(e) = res {
+                bail!("config file {}: {}", config.path().display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Construct a getopts object with the registered options that satisfy
+     * `filter`.
+     */
+    fn getopts(
+        &self,
+        filter: impl Fn(&OptionInfo) -> bool,
+    ) -> getopts::Options {
+        getopts_for(&self.option_info, filter)
+    }
+
+    fn command_summaries(&self) -> Vec<describe::CommandSummary> {
+        self.commands
+            .iter()
+            .map(|ci| describe::CommandSummary {
+                name: ci.name.to_string(),
+                aliases: ci.aliases.clone(),
+                hidden_aliases: ci.hidden_aliases.clone(),
+                desc: ci.desc.to_string(),
+                visible: ci.visible,
+                default: ci.default,
+            })
+            .collect()
+    }
+
+    fn describe(
+        &self,
+        matches: Option<getopts::Matches>,
+    ) -> describe::LevelInfo {
+        let groups = |groups: &Option<Vec<Vec<OptionPair>>>| {
+            groups
+                .iter()
+                .flatten()
+                .map(|opts| opts.iter().map(OptionPair::to_pair).collect())
+                .collect()
+        };
+
+        describe::LevelInfo {
+            names: self.names.clone(),
+            synopsis: self.usage_info().synopsis(),
+            args: self.args.clone().unwrap_or_default(),
+            free: matches.map(|m| m.free),
+            commands: self.command_summaries(),
+            options: self.option_info.clone(),
+            positionals: self.positionals.clone(),
+            mutex: groups(&self.options_mutex),
+            requires: self
+                .options_requires
+                .iter()
+                .flatten()
+                .map(|(opt, needs)| (opt.to_pair(), needs.to_pair()))
+                .collect(),
+            at_least_one: groups(&self.options_any),
+            exactly_one: groups(&self.options_one),
+            columns: self
+                .table
+                .as_ref()
+                .map(|t| {
+                    t.columns()
+                        .iter()
+                        .map(|c| describe::ColumnSummary {
+                            name: c.name.to_string(),
+                            width: c.width,
+                            default: c.default,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /**
+     * Locate the command nominated by the user.  If prefix matching is
+     * enabled and the name is a prefix of more than one command, the names of
+     * all of those commands are returned as an error.
+     */
+    fn find_command(
+        &self,
+        want: &str,
+    ) -> std::result::Result<Option<usize>, Vec<String>> {
+        if let Some(pos) = self.commands.iter().position(|ci| ci.matches(want))
+        {
+            return Ok(Some(pos));
+        }
+
+        if !self.prefix_match || want.is_empty() {
+            return Ok(None);
+        }
+
+        /*
+         * Hidden commands are never matched by prefix, as the user would have
+         * no way to know what they were choosing between.
+         */
+        let found = self
+            .commands
+            .iter()
+            .enumerate()
+            .filter(|(_, ci)| ci.visible && ci.name.starts_with(want))
+            .map(|(pos, _)| pos)
+            .collect::<Vec<_>>();
+        match found.as_slice() {
+            [] => Ok(None),
+            [pos] => Ok(Some(*pos)),
+            _ => Err(found
+                .iter()
+                .map(|pos| self.commands[*pos].name.to_string())
+                .collect()),
+        }
+    }
+
+    /**
+     * The names and aliases of the visible commands at this level.
+     */
+    fn visible_names(&self) -> impl Iterator<Item = &String> {
+        self.commands
+            .iter()
+            .filter(|ci| ci.visible)
+            .flat_map(|ci| std::iter::once(&ci.name).chain(&ci.aliases))
+    }
+
+    /**
+     * If the consumer has nominated a default command and the user has
+     * provided arguments that do not begin with a command, insert the name
+     * of the default command ahead of them so that they are passed to it;
+     * e.g., so that "trial thing -o name" is the same as "trial thing list -o
+     * name".  The arguments begin after any options for this level, and with
+     * either an option this level does not accept or a word that is not the
+     * name of a command.  A word that is close to the name of a command is
+     * left alone, so that the likely typo is reported instead.
+     */
+    fn insert_default(&mut self) -> Result<()> {
+        let name = match self.commands.iter().find(|ci| ci.default) {
+            Some(ci) => ci.name.to_string(),
+            None => return Ok(()),
+        };
+
+        /*
+         * Option parsing stops at the first free argument, so we look for the
+         * shortest run of arguments that does not parse as options for this
+         * level alone.  An option that needs a value is incomplete until we
+         * reach the value.
+         */
+        let args = self.raw_args();
+        let options = self.getopts(|_| true);
+        let mut found = None;
+        for i in 0..args.len() {
+            if args[i] == "--" {
+                return Ok(());
+            }
+            match options.parse(&args[..=i]) {
+                Ok(m) if m.free.is_empty() => (),
+                Ok(_) => {
+                    found = Some((i, false));
+                    break;
+                }
+                Err(getopts::Fail::UnrecognizedOption(_)) => {
+                    found = Some((i, true));
+                    break;
+                }
+                Err(getopts::Fail::ArgumentMissing(_)) => (),
+                Err(_) => return Ok(()),
+            }
+        }
+        let (pos, option) = if let Some(found) = found {
+            found
+        } else {
+            return Ok(());
+        };
+
+        if !option {
+            let want = if let Some(want) = args[pos].to_str() {
+                want
+            } else {
+                return Ok(());
+            };
+            if (self.builtin_help() && want == HELP_COMMAND)
+                || !matches!(self.find_command(want), Ok(None))
+                || suggest::suggest(want, self.visible_names()).is_some()
+                || self.find_plugin(want).is_some()
+            {
+                return Ok(());
+            }
+        }
+
+        let mut args = self.string_args()?;
+        args.insert(pos, name);
+        self.args = Some(args);
+        Ok(())
+    }
+
+    /**
+     * Parse options for this command level and select the next command.  The
+     * best way to call this routine is using the `sel!()` macro, which handles
+     * the early return and exit-on-failure conditions automatically.
+     */
+    pub fn select(mut self) -> Result<Option<Selection<C>>> {
+        if self.commands.is_empty() {
+            bail!("no commands provided by consumer");
+        }
+
+        if let Some(d) = self.shared.describe.clone() {
+            return Ok(self.select_describe(d));
+        }
+
+        self.prepare_config()?;
+        self.expand_aliases()?;
+        self.insert_default()?;
+
+        let mut args = args!(self);
+        let matches = &mut args.matches;
+
+        /*
+         * Determine which command the user is trying to run.
+         */
+        if matches.free.is_empty() {
+            /*
+             * If the consumer has nominated a default command, run it as if
+             * the user had named it.  Arguments for it have already been
+             * dealt with by insert_default().
+             */
+            if let Some(ci) = self.commands.iter().find(|ci| ci.default) {
+                matches.free.push(ci.name.to_string());
+            } else {
+                bad_args!(self, "choose a command");
+            }
+        }
+
+        if self.builtin_help() && matches.free[0] == HELP_COMMAND {
+            /*
+             * The built-in help command shows the usage for the nominated
+             * command; e.g., "help thing list" is the same as "thing list
+             * --help".  Without arguments, we list all of the commands below
+             * this level instead.
+             */
+            matches.free.remove(0);
+            if matches.free.is_empty() {
+                let d = describe::Describer::tree(false);
+                d.record(self.describe(Some(matches.clone())));
+                let stdout = self.stdout();
+                self.shared.describe = Some(d);
+                self.shared.stdout = Output::capture();
+                self.shared.stderr = Output::capture();
+                return Ok(Some(self.into_walk(args.matches, Some(stdout))));
+            }
+            matches.free.push("--help".to_string());
+        }
+
+        let want = matches.free[0].as_str();
+        match self.find_command(want) {
+            Ok(Some(pos)) => {
+                return Ok(Some(Selection {
+                    names: self.names,
+                    private: self.private,
+                    shared: self.shared,
+                    command: self.commands.swap_remove(pos),
+                    walk: Vec::new(),
+                    help: None,
+                    plugin: None,
+                    matches: args.matches,
+                }));
+            }
+            Ok(None) => (),
+            Err(candidates) => {
+                bad_args!(
+                    self,
+                    "command \"{}\" is ambiguous; could be: {}",
+                    want,
+                    candidates.join(", ")
+                );
+            }
+        }
+
+        if let Some(path) = self.find_plugin(want) {
+            /*
+             * The selection for an external command has no handler of its
+             * own to call, so it records the command with one that does
+             * nothing.
+             */
+            return Ok(Some(Selection {
+                command: CommandInfo {
+                    name: want.to_string(),
+                    aliases: Vec::new(),
+                    hidden_aliases: Vec::new(),
+                    desc: String::new(),
+                    func: caller(|_| async { Ok(()) }),
+                    visible: true,
+                    default: false,
+                    mapped: false,
+                },
+                names: self.names,
+                private: self.private,
+                shared: self.shared,
+                walk: Vec::new(),
+                help: None,
+                plugin: Some(path),
+                matches: args.matches,
+            }));
+        }
+
+        let dym = suggest::did_you_mean(want, self.visible_names());
+        let msg = format!("command \"{}\" not understood{}", want, dym);
+        Err(self.usage_error(&msg).into())
+    }
+
+    fn select_describe(
+        mut self,
+        d: describe::Describer,
+    ) -> Option<Selection<C>> {
+        if !d.is_tree() {
+            self.insert_default().ok();
+        }
+        let mut matches = self.parse_matches().ok();
+        if let Some(m) = &matches {
+            self.shared.globals.record(&self.option_info, m);
+        }
+
+        if d.is_tree() {
+            /*
+             * When describing the whole tree, record this level and then
+             * arrange to visit each of the visible sub-commands in turn.
+             */
+            d.record(self.describe(matches.clone()));
+            if let Some(matches) = matches {
+                if self.commands.iter().any(|ci| d.visits(ci.visible)) {
+                    return Some(self.into_walk(matches, None));
+                }
+            }
+            return None;
+        }
+
+        /*
+         * Otherwise, descend into the nominated command if there is one.  If
+         * there is not, this is the deepest level and we record what we know
+         * about it.
+         */
+        if let Some(m) = matches.as_mut() {
+            if self.builtin_help()
+                && m.free.first().map(String::as_str) == Some(HELP_COMMAND)
+            {
+                m.free.remove(0);
+            }
+        }
+        let pos = matches
+            .as_ref()
+            .and_then(|m| m.free.first())
+            .and_then(|want| self.find_command(want).ok().flatten());
+        if let (Some(pos), Some(matches)) = (pos, &matches) {
+            return Some(Selection {
+                names: self.names,
+                private: self.private,
+                shared: self.shared,
+                command: self.commands.swap_remove(pos),
+                walk: Vec::new(),
+                help: None,
+                plugin: None,
+                matches: matches.clone(),
+            });
+        }
+        d.record(self.describe(matches));
+        None
+    }
+
+    /**
+     * Produce a selection that will visit every sub-command of this level
+     * when run, rather than just the one nominated by the user.
+     */
+    fn into_walk(
+        mut self,
+        matches: getopts::Matches,
+        help: Option<Output>,
+    ) -> Selection<C> {
+        let mut walk = std::mem::take(&mut self.commands);
+        let command = walk.remove(0);
+        Selection {
+            names: self.names,
+            private: self.private,
+            shared: self.shared,
+            command,
+            walk,
+            help,
+            plugin: None,
+            matches,
+        }
+    }
+
+    /**
+     * The built-in "help" command is available at every level with
+     * sub-commands, unless the consumer has registered their own.
+     */
+    fn builtin_help(&self) -> bool {
+        !self.commands.is_empty()
+            && !self.commands.iter().any(|ci| ci.matches(HELP_COMMAND))
+    }
+
+    pub fn usage(&self) {
+        write!(self.stdout(), "{}", self.gen_usage()).ok();
+    }
+
+    /**
+     * Construct a usage error for this level, including the usage information
+     * and the provided message.  This is generally used through the
+     * `bad_args!()` macro.
+     */
+    pub fn usage_error(&self, msg: &str) -> UsageError {
+        UsageError {
+            names: self.names.clone(),
+            usage: self.gen_usage(),
+            message: msg.to_string(),
+        }
+    }
+
+    /**
+     * Take what is needed to render the usage information for this level.
+     */
+    fn usage_info(&self) -> Usage {
+        Usage {
+            names: self.names.clone(),
+            usage_args: self.usage_args.clone(),
+            usage_opts: self.usage_opts,
+            commands: self.command_summaries(),
+            builtin_help: self.builtin_help(),
+            aliases: self.aliases().to_vec(),
+            plugin_path: self.plugin_path(),
+            positionals: self.positionals.clone(),
+            option_info: self.option_info.clone(),
+            options_mutex: self.options_mutex.clone().unwrap_or_default(),
+            options_requires: self.options_requires.clone().unwrap_or_default(),
+            options_any: self.options_any.clone().unwrap_or_default(),
+            options_one: self.options_one.clone().unwrap_or_default(),
+            columns: self
+                .table
+                .as_ref()
+                .map(|t| t.column_names())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn gen_usage(&self) -> String {
+        self.usage_info().render()
+    }
+}
+
+/**
+ * Everything needed to render the usage information for a level.  This is
+ * kept by `Arguments` so that the typed option accessors can report usage
+ * errors, while the usage itself is only rendered if it is needed.
+ */
+#[derive(Clone)]
+struct Usage {
+    names: Vec<String>,
+    usage_args: Option<String>,
+    usage_opts: bool,
+    commands: Vec<describe::CommandSummary>,
+    builtin_help: bool,
+    aliases: Vec<(String, Vec<String>)>,
+    plugin_path: Option<OsString>,
+    positionals: Vec<Positional>,
+    option_info: Vec<OptionInfo>,
+    options_mutex: Vec<Vec<OptionPair>>,
+    options_requires: Vec<(OptionPair, OptionPair)>,
+    options_any: Vec<Vec<OptionPair>>,
+    options_one: Vec<Vec<OptionPair>>,
+    columns: Vec<String>,
+}
+
+impl Usage {
+    fn synopsis(&self) -> String {
+        /*
+         * The usage synopsis starts with the first level (the command name) and
+         * then includes each level down to the present level:
+         */
+        let mut out = self.names.join(" ");
+        if self.usage_opts {
+            /*
+             * If this level specifies any options, mention that in the usage
+             * synposis.
+             */
+            out.push_str(" [OPTS]");
+        }
+        if !self.commands.is_empty() {
+            /*
+             * If this is level is not terminal (i.e., it has further
+             * sub-levels) then include that in the synopsis:
+             */
+            if self.commands.iter().any(|ci| ci.default) {
+                out.push_str(" [COMMAND]");
+            } else {
+                out.push_str(" COMMAND");
+            }
+        }
+        //out.push_str(" [OPTS]");
+        if !self.positionals.is_empty() && self.commands.is_empty() {
+            /*
+             * Declared positional arguments take the place of the free-form
+             * description of the arguments.
+             */
+            for p in self.positionals.iter() {
+                out.push_str(&format!(" {}", p.synopsis()));
+            }
+        } else if let Some(usage_args) = &self.usage_args {
+            out.push_str(&format!(" {}", usage_args));
+        }
+        out
+    }
+
+    /**
+     * Describe the relationships between options at this level, for
+     * inclusion in the usage output.
+     */
+    fn constraints(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        for opts in self.options_mutex.iter() {
+            out.push(format!(
+                "{} are mutually exclusive",
+                join_pairs(opts, " and ")
+            ));
+        }
+        for (opt, needs) in self.options_requires.iter() {
+            out.push(format!("{} requires {}", opt, needs));
+        }
+        for opts in self.options_any.iter() {
+            out.push(format!(
+                "at least one of {} is required",
+                join_pairs(opts, ", ")
+            ));
+        }
+        for opts in self.options_one.iter() {
+            out.push(format!(
+                "exactly one of {} is required",
+                join_pairs(opts, ", ")
+            ));
+        }
+        out
+    }
+
+    /**
+     * List the external commands available at the level, if they are
+     * enabled.  Commands that would be shadowed by a registered command, or
+     * that belong to the level of a registered command, are not included.
+     */
+    fn plugins(&self) -> Vec<String> {
+        plugin::list(self.plugin_path.as_deref(), &self.names.join("-"))
+            .into_iter()
+            .filter(|cmd| {
+                let shadowed = (self.builtin_help && cmd == HELP_COMMAND)
+                    || self.commands.iter().any(|ci| {
+                        ci.name == *cmd
+                            || ci.aliases.contains(cmd)
+                            || ci.hidden_aliases.contains(cmd)
+                            || cmd.starts_with(&format!("{}-", ci.name))
+                    });
+                !shadowed
+            })
+            .collect()
+    }
+
+    fn render(&self) -> String {
+        let mut out = format!("Usage: {}\n", self.synopsis());
+        if !self.commands.is_empty() {
+            out.push_str("\nCommands:\n");
+            for cmd in self.commands.iter() {
+                if !cmd.visible {
+                    continue;
+                }
+                let cn = if !cmd.aliases.is_empty() {
+                    format!("{} ({})", cmd.name, cmd.aliases.join(", "))
+                } else {
+                    cmd.name.to_string()
+                };
+                if cmd.default {
+                    out.push_str(&format!(
+                        "    {:<19} {} (default)\n",
+                        cn, cmd.desc
+                    ));
+                } else {
+                    out.push_str(&format!("    {:<19} {}\n", cn, cmd.desc));
+                }
+            }
+            if self.builtin_help {
+                out.push_str(&format!(
+                    "    {:<19} {}\n",
+                    HELP_COMMAND, "show help for commands"
+                ));
+            }
+        }
+        let aliases = &self.aliases;
+        if !aliases.is_empty() {
+            out.push_str("\nAliases:\n");
+            for (name, words) in aliases.iter() {
+                out.push_str(&format!(
+                    "    {:<19} {}\n",
+                    name,
+                    words.join(" ")
+                ));
+            }
+        }
+        let plugins = self.plugins();
+        if !plugins.is_empty() {
+            out.push_str("\nExternal commands:\n");
+            for cmd in plugins {
+                out.push_str(&format!("    {}\n", cmd));
+            }
+        }
+        if !self.positionals.is_empty() && self.commands.is_empty() {
+            out.push_str("\nArguments:\n");
+            for p in self.positionals.iter() {
+                out.push_str(&format!("    {:<19} {}\n", p.name, p.desc));
+            }
+        }
+        /*
+         * Inherited global options are accepted at this level, but are shown
+         * separately from the options that belong to it.
+         */
+        let mut out =
+            getopts_for(&self.option_info, |o| !o.inherited).usage(&out);
+        out.push('\n');
+        let constraints = self.constraints();
+        if !constraints.is_empty() {
+            out.push_str("Constraints:\n");
+            for c in constraints {
+                out.push_str(&format!("    {}\n", c));
+            }
+            out.push('\n');
+        }
+        if self.option_info.iter().any(|o| o.inherited) {
+            let inherited = getopts_for(&self.option_info, |o| o.inherited);
+            out.push_str(&inherited.usage_with_format(|rows| {
+                format!(
+                    "Global options:\n{}\n\n",
+                    rows.collect::<Vec<_>>().join("\n")
+                )
+            }));
+        }
+        if !self.columns.is_empty() {
+            out.push_str("Columns:\n");
+            for col in self.columns.iter() {
+                out.push_str(&format!("    {:<19}\n", col));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+pub struct Selection<C: LevelContext> {
+    private: Private<C>,
+    shared: Shared,
+    names: Vec<String>,
+    command: CommandInfo<C>,
+    /*
+     * When describing the command tree, the remaining sub-commands to visit
+     * after the first, and where to print the tree if this is the top of a
+     * walk for the built-in help command.
+     */
+    walk: Vec<CommandInfo<C>>,
+    help: Option<Output>,
+    /*
+     * The executable to run instead of a handler, if the user nominated an
+     * external command.
+     */
+    plugin: Option<PathBuf>,
+    matches: getopts::Matches,
+}
+
+impl<C: LevelContext> Selection<C> {
+    pub fn opts(&self) -> &getopts::Matches {
+        &self.matches
+    }
+
+    /**
+     * The values of global options provided at this level and above.
+     */
+    pub fn globals(&self) -> &Globals {
+        &self.shared.globals
+    }
+
+    pub async fn run(self) -> Result<()> {
+        if self
+            .shared
+            .describe
+            .as_ref()
+            .map(|d| d.is_tree())
+            .unwrap_or(false)
+        {
+            return self.run_walk().await;
+        }
+
+        let dispatcher = self.dispatcher();
+        let run: HandlerFuture = if let Some(path) = self.plugin {
+            let args = self.matches.free[1..].to_vec();
+            let stdout = self.shared.stdout;
+            let stderr = self.shared.stderr;
+            Box::pin(blocking::unblock(move || {
+                plugin::run(&path, &args, stdout, stderr)
+            }))
+        } else {
+            let mut names = self.names;
+            names.push(self.command.name);
+            let l = Level::new_sub(
+                names,
+                self.private.into_inner(),
+                Some(self.matches.free[1..].to_vec()),
+                self.shared,
+            );
+            (self.command.func)(l)
+        };
+        dispatcher.run(run).await
+    }
+
+    /**
+     * The name of the selected command, rather than any alias or prefix of
+     * it that the user provided.
+     */
+    pub fn command_name(&self) -> &str {
+        &self.command.name
+    }
+
+    /**
+     * Whether the selected command was registered with `Level::cmd_with()`,
+     * and so needs a context object of a different type.
+     */
+    pub fn is_mapped(&self) -> bool {
+        self.command.mapped
+    }
+
+    /**
+     * Whether the selected command is `cmd`, which was registered with
+     * `Level::cmd_with()` at this level.
+     */
+    fn selects<D: LevelContext>(&self, cmd: &Mapped<D>) -> bool {
+        self.shared.describe.is_none()
+            && self.plugin.is_none()
+            && cmd.names.split_last()
+                == Some((&self.command.name, self.names.as_slice()))
+    }
+
+    /**
+     * Run the selected command with the context object produced by `map` from
+     * the context object for this level, if it is the command `cmd`.  Any
+     * other command is run as with `run()`, without calling `map`, so that a
+     * level with both kinds of command can run every selection this way.  The
+     * context object for this level is kept until the command is done; e.g.,
+     * so that the interactive shell can pass it to the command on the next
+     * line.
+     */
+    pub async fn run_with<D, F>(self, cmd: &Mapped<D>, map: F) -> Result<()>
+    where
+        D: LevelContext,
+        F: FnOnce(&C) -> D,
+    {
+        if !self.selects(cmd) {
+            return self.run().await;
+        }
+
+        let dispatcher = self.dispatcher();
+        let private = map(self.context());
+        let run = Self::run_mapped(
+            self.names,
+            self.command.name,
+            private,
+            self.shared,
+            self.matches,
+            Arc::clone(&cmd.func),
+        );
+        dispatcher.run(run).await
+    }
+
+    /**
+     * Run the selected command with the context object produced by the
+     * asynchronous function `map`; e.g., to connect to a remote service.  If
+     * `map` fails, the error is returned without running the command.
+     * Otherwise identical to `run_with()`.
+     */
+    pub async fn run_with_async<D, F, Fut>(
+        self,
+        cmd: &Mapped<D>,
+        map: F,
+    ) -> Result<()>
+    where
+        D: LevelContext,
+        F: FnOnce(&C) -> Fut,
+        Fut: Future<Output = Result<D>>,
+    {
+        if !self.selects(cmd) {
+            return self.run().await;
+        }
+
+        let dispatcher = self.dispatcher();
+        let private = map(self.context()).await?;
+        let run = Self::run_mapped(
+            self.names,
+            self.command.name,
+            private,
+            self.shared,
+            self.matches,
+            Arc::clone(&cmd.func),
+        );
+        dispatcher.run(run).await
+    }
+
+    fn run_mapped<D: LevelContext>(
+        mut names: Vec<String>,
+        name: String,
+        private: D,
+        shared: Shared,
+        matches: getopts::Matches,
+        func: Caller<D>,
+    ) -> HandlerFuture {
+        names.push(name);
+        let l = Level::new_sub(
+            names,
+            private,
+            Some(matches.free[1..].to_vec()),
+            shared,
+        );
+        func(l)
+    }
+
+    fn dispatcher(&self) -> Dispatcher {
+        let mut names = self.names.clone();
+        names.push(self.command.name.clone());
+        Dispatcher {
+            dispatch: Dispatch {
+                names,
+                desc: self.command.desc.clone(),
+                external: self.plugin.is_some(),
+                matches: Arc::new(self.matches.clone()),
+                globals: self.shared.globals.clone(),
+            },
+            /*
+             * While describing the command tree, handlers are called only to
+             * describe their levels, so there is nothing for hooks to do.
+             */
+            hooks: if self.shared.describe.is_some() {
+                Vec::new()
+            } else {
+                self.shared.hooks.clone()
+            },
+            shared: self.shared.clone(),
+        }
+    }
+
+    async fn run_walk(self) -> Result<()> {
+        let d = self.shared.describe.clone().unwrap();
+
+        /*
+         * Each sub-level handler hands the context object back through the
+         * describer once it is done, so that we can pass it on to the next.
+         * If a handler fails before describing its level, we cannot continue,
+         * and rather than present part of the tree as if it were the whole,
+         * the walk fails.  The context object, if any, is handed back to
+         * wherever it came from when this selection is dropped.
+         */
+        let mut private = self.private;
+        let absent = private.value.is_none();
+        for ci in std::iter::once(self.command).chain(self.walk) {
+            if !d.visits(ci.visible) {
+                continue;
+            }
+
+            let mut names = self.names.clone();
+            names.push(ci.name.to_string());
+            let l = Level::new_level(
+                names.clone(),
+                private.take(),
+                Some(Vec::new()),
+                self.shared.clone(),
+            );
+            let res = (ci.func)(l).await;
+            if let Some(c) = d.take::<C>() {
+                private.put(c);
+            }
+            d.complete(&names, res)?;
+            if private.value.is_none() && !absent {
+                return Err(describe::Incomplete::error(
+                    &names,
+                    Err(anyhow::anyhow!(
+                        "the handler did not hand back the context object"
+                    )),
+                ));
+            }
+        }
+
+        if let Some(mut stdout) = self.help {
+            write!(stdout, "{}", describe::hierarchy(&d.levels())).ok();
+        }
+        Ok(())
+    }
+
+    /**
+     * Access the context object, as per `Level::context()`.
+     */
+    pub fn context(&self) -> &C {
+        self.private.get()
+    }
+
+    pub fn context_mut(&mut self) -> &mut C {
+        self.private.get_mut()
+    }
+
+    pub fn stdout(&self) -> Output {
+        self.shared.stdout.clone()
+    }
+
+    pub fn stderr(&self) -> Output {
+        self.shared.stderr.clone()
+    }
+
+    /**
+     * The logger configured by the standard logging options, as per
+     * `Level::logger()`.
+     */
+    pub fn logger(&self) -> slog::Logger {
+        self.shared.logger()
+    }
+}
+
+/**
+ * The selected command, as it is run through any hooks.
+ */
+struct Dispatcher {
+    dispatch: Dispatch,
+    hooks: Vec<hook::Hook>,
+    shared: Shared,
+}
+
+impl Dispatcher {
+    /**
+     * Run the future for the command through any hooks.  The command is the
+     * position in the tree from which errors are reported until it completes
+     * successfully, after which the position reverts to the level above.
+     */
+    async fn run(self, run: HandlerFuture) -> Result<()> {
+        let pos = self.shared.position(self.dispatch.names.clone());
+        let prev = self.shared.set_position(Some(pos));
+        let res = hook::wrap(&self.hooks, self.dispatch, run).await;
+        if res.is_ok() {
+            self.shared.set_position(prev);
+        }
+        res
+    }
+}
+
+pub struct Arguments {
+    /*
+     * What is needed to render the usage information is kept so that the
+     * typed option accessors can report usage errors for the level.
+     */
+    usage: Usage,
+    positionals: Vec<(String, Vec<String>)>,
+    matches: getopts::Matches,
+    table: Option<table::TableBuilder>,
+    globals: Globals,
+}
+
+impl Arguments {
+    pub fn opts(&self) -> &getopts::Matches {
+        &self.matches
+    }
+
+    /**
+     * The values of global options provided at this level and above.
+     */
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.matches.free
+    }
+
+    /**
+     * The value of a declared positional argument, if one was provided.  For
+     * a variadic argument, this is the first value.
+     */
+    pub fn positional(&self, name: &str) -> Option<&str> {
+        self.positionals(name).first().map(String::as_str)
+    }
+
+    /**
+     * Every value provided for a declared positional argument.
+     */
+    pub fn positionals(&self, name: &str) -> &[String] {
+        self.positionals
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, vals)| vals.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn add_column(&mut self, name: &str, width: usize, def: bool) {
+        self.table.as_mut().unwrap().add_column(name, width, def);
+    }
+
+    pub fn set_column_default(&mut self, name: &str, def: bool) {
+        self.table.as_mut().unwrap().set_column_default(name, def);
+    }
+
+    pub fn table(&self) -> table::Table {
+        self.table.as_ref().unwrap().build()
+    }
+
+    /**
+     * Parse the value of an option with `FromStr`, if it was provided.  If
+     * the value cannot be parsed, a `UsageError` that names the option is
+     * returned.
+     */
+    pub fn opt_parse<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.opt_convert(name, |s| s.parse::<T>().map_err(|e| e.to_string()))
+    }
+
+    pub fn opt_u64(&self, name: &str) -> Result<Option<u64>> {
+        self.opt_parse::<u64>(name)
+    }
+
+    /**
+     * Parse the value of an option as a duration; e.g., "10m" or "1h30m".
+     * Otherwise identical to `opt_parse()`.
+     */
+    pub fn opt_duration(
+        &self,
+        name: &str,
+    ) -> Result<Option<std::time::Duration>> {
+        self.opt_convert(name, units::parse_duration)
+    }
+
+    /**
+     * Parse the value of an option as a size in bytes; e.g., "512" or "4G".
+     * Otherwise identical to `opt_parse()`.
+     */
+    pub fn opt_bytes(&self, name: &str) -> Result<Option<u64>> {
+        self.opt_convert(name, units::parse_bytes)
+    }
+
+    /**
+     * Split the value of an option on commas; e.g., "-t a,b" produces ["a",
+     * "b"].  If the option may be repeated, the lists from each use are
+     * concatenated.
+     */
+    pub fn opt_list(&self, name: &str) -> Vec<String> {
+        self.matches
+            .opt_strs(name)
+            .iter()
+            .flat_map(|s| s.split(','))
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn opt_convert<T>(
+        &self,
+        name: &str,
+        convert: impl Fn(&str) -> std::result::Result<T, String>,
+    ) -> Result<Option<T>> {
+        let val = if let Some(val) = self.matches.opt_str(name) {
+            val
+        } else {
+            return Ok(None);
+        };
+
+        match convert(&val) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => {
+                let opt = self
+                    .usage
+                    .option_info
+                    .iter()
+                    .find(|o| o.short == name || o.long == name)
+                    .map(|o| o.pair().to_string())
+                    .unwrap_or_default();
+                Err(UsageError {
+                    names: self.usage.names.clone(),
+                    usage: self.usage.render(),
+                    message: format!(
+                        "invalid value \"{}\" for option {}: {}",
+                        val, opt, e
+                    ),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Level, UsageError};
+    use crate::prelude::*;
+    use crate::testing::invoke;
+    use anyhow::{Context, Result};
+    use std::ffi::OsString;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    pub(crate) fn level(args: &[&str]) -> Level<()> {
+        Level::new_sub(
+            vec!["trial".to_string(), "sub".to_string()],
+            (),
+            Some(args.iter().map(|s| s.to_string()).collect()),
+            Default::default(),
+        )
+    }
+
+    /**
+     * Create a level for `args` as per `level()`, and register commands and
+     * options with `register`.
+     */
+    pub(crate) fn level_with<F>(args: &[&str], register: F) -> Level<()>
+    where
+        F: Fn(&mut Level<()>),
+    {
+        let mut l = level(args);
+        register(&mut l);
+        l
+    }
+
+    /**
+     * Create a level as per `level_with()`, and return the message from the
+     * usage error produced when selecting a command, or when parsing the
+     * arguments at a level without commands.
+     */
+    pub(crate) fn usage_message<F>(args: &[&str], register: F) -> String
+    where
+        F: Fn(&mut Level<()>),
+    {
+        let mut l = level_with(args, register);
+        let e = if l.commands.is_empty() {
+            l.parse().err()
+        } else {
+            l.select().err()
+        };
+        let e = e.expect("usage error");
+        let ue = e.downcast_ref::<UsageError>().expect("usage error");
+        ue.message().to_string()
+    }
+
+    /**
+     * Register options with `register`, with `env` as the environment.
+     */
+    fn with_env<'a>(
+        env: &'a [(&'a str, &'a str)],
+        register: fn(&mut Level<()>),
+    ) -> impl Fn(&mut Level<()>) + 'a {
+        move |l| {
+            l.shared.env = Some(Arc::new(
+                env.iter()
+                    .map(|(k, v)| (k.to_string(), OsString::from(v)))
+                    .collect(),
+            ));
+            register(l);
+        }
+    }
+
+    #[test]
+    fn usage_error_required() {
+        let mut l = level(&["-a", "x"]);
+        l.reqopt("a", "", "first letter", "LETTER");
+        l.reqopt("b", "", "second letter", "LETTER");
+
+        let e = l.parse().err().expect("parse should fail");
+        let ue = e.downcast_ref::<UsageError>().expect("usage error");
+        assert_eq!(ue.message(), "required options missing: -b");
+        assert_eq!(ue.names(), &["trial", "sub"]);
+        assert!(ue.usage().starts_with("Usage: trial sub [OPTS]"));
+    }
+
+    #[test]
+    fn usage_error_unknown_command() {
+        let mut l = level(&["lsit"]);
+        l.cmd("list", "list things", |_| Box::pin(async { Ok(()) }))
+            .unwrap();
+
+        let e = l.select().err().expect("select should fail");
+        let ue = e.downcast_ref::<UsageError>().expect("usage error");
+        assert_eq!(
+            ue.message(),
+            "command \"lsit\" not understood; did you mean \"list\"?"
+        );
+    }
+
+    #[test]
+    fn select_default() {
+        fn register(l: &mut Level<()>) {
+            l.cmd("list", "list things", |_| Box::pin(async { Ok(()) }))
+                .unwrap();
+            l.cmd("push", "push things", |_| Box::pin(async { Ok(()) }))
+                .unwrap();
+        }
+        fn register_default(l: &mut Level<()>) {
+            register(l);
+            l.optopt("n", "", "name", "NAME");
+            l.default_cmd("list").unwrap();
+        }
+
+        assert_eq!(usage_message(&[], register), "choose a command");
+        assert!(level_with(&[], register).default_cmd("pop").is_err());
+
+        let mut l = level_with(&[], register);
+        l.default_cmd("list").unwrap();
+        assert!(l.default_cmd("push").is_err());
+        let usage = l.gen_usage();
+        assert!(usage.starts_with("Usage: trial sub [COMMAND]"));
+        assert!(usage.contains("list                list things (default)\n"));
+        let sel = l.select().unwrap().unwrap();
+        assert_eq!(sel.command.name, "list");
+
+        let l = level_with(&["push"], register_default);
+        let sel = l.select().unwrap().unwrap();
+        assert_eq!(sel.command.name, "push");
+
+        /*
+         * Arguments that do not begin with a command go to the default.
+         */
+        for args in [&["-o", "name"][..], &["name"], &["-o", "name", "push"]] {
+            let l = level_with(args, register_default);
+            let sel = l.select().unwrap().unwrap();
+            assert_eq!(sel.command.name, "list");
+            assert_eq!(sel.matches.free[1..], *args);
+        }
+
+        let l = level_with(&["-n", "x", "-o", "name"], register_default);
+        let sel = l.select().unwrap().unwrap();
+        assert_eq!(sel.command.name, "list");
+        assert_eq!(sel.matches.opt_str("n").as_deref(), Some("x"));
+        assert_eq!(sel.matches.free, ["list", "-o", "name"]);
+
+        /*
+         * A likely typo is still reported as such.
+         */
+        assert_eq!(
+            usage_message(&["psuh"], register_default),
+            "command \"psuh\" not understood; did you mean \"push\"?"
+        );
+    }
+
+    #[test]
+    fn select_prefix() {
+        fn register(l: &mut Level<()>) {
+            l.cmd("list", "list things", |_| Box::pin(async { Ok(()) }))
+                .unwrap();
+            l.cmd("lint", "lint things", |_| Box::pin(async { Ok(()) }))
+                .unwrap();
+            l.hcmd("info", "hidden", |_| Box::pin(async { Ok(()) }))
+                .unwrap();
+            l.prefix_match(true);
+        }
+
+        let sel = level_with(&["lis"], register).select().unwrap().unwrap();
+        assert_eq!(sel.command.name, "list");
+
+        let sel = level_with(&["info"], register).select().unwrap().unwrap();
+        assert_eq!(sel.command.name, "info");
+
+        assert!(usage_message(&["inf"], register)
+            .starts_with("command \"inf\" not understood"));
+        assert_eq!(
+            usage_message(&["li"], register),
+            "command \"li\" is ambiguous; could be: list, lint"
+        );
+    }
+
+    async fn do_kind(mut l: Level<()>, kind: String) -> Result<()> {
+        no_args!(l);
+        writeln!(l.stdout(), "listed {}", kind)?;
+        Ok(())
+    }
+
+    async fn do_kinds(mut l: Level<()>) -> Result<()> {
+        for kind in ["box", "crate"] {
+            let desc = format!("list {}es", kind);
+            l.cmd(kind, &desc, move |l| do_kind(l, kind.to_string()))?;
+        }
+        let s = sel!(l);
+        tokio::spawn(s.run()).await?
+    }
+
+    #[tokio::test]
+    async fn closure_handlers() {
+        let res = invoke(do_kinds, &["top", "crate"]).await;
+        assert_eq!(res.stdout(), "listed crate\n");
+
+        let res = invoke(cmd!(do_kinds), &["top", "box"]).await;
+        assert_eq!(res.stdout(), "listed box\n");
+    }
+
+    struct Client {
+        cluster: String,
+    }
+
+    async fn do_status(mut l: Level<Client>) -> Result<()> {
+        no_args!(l);
+        writeln!(l.stdout(), "status of {}", l.context().cluster)?;
+        Ok(())
+    }
+
+    async fn do_node_list(mut l: Level<Client>) -> Result<()> {
+        no_args!(l);
+        writeln!(l.stdout(), "nodes of {}", l.context().cluster)?;
+        Ok(())
+    }
+
+    async fn do_nodes(mut l: Level<Client>) -> Result<()> {
+        l.cmd("list", "list nodes", do_node_list)?;
+        sel!(l).run().await
+    }
+
+    async fn do_ping(mut l: Level<()>) -> Result<()> {
+        no_args!(l);
+        writeln!(l.stdout(), "pong")?;
+        Ok(())
+    }
+
+    async fn do_cluster(mut l: Level<()>) -> Result<()> {
+        let status = l.cmd_with("status", "show status", do_status)?;
+        let nodes = l.cmd_with("nodes", "manage nodes", do_nodes)?;
+        l.cmd("ping", "check the cluster", do_ping)?;
+        l.optopt("n", "name", "cluster name", "NAME");
+        l.optflag("a", "async", "connect asynchronously");
+        l.optflag("r", "run", "run without a new context");
+        l.optflag("w", "wrong", "run with the wrong command");
+        let s = sel!(l);
+        let cluster = s.opts().opt_str("n").unwrap_or_default();
+        writeln!(s.stderr(), "{} {}", s.command_name(), s.is_mapped())?;
+        let cmd = if s.command_name() == "nodes" {
+            &nodes
+        } else {
+            &status
+        };
+        if s.opts().opt_present("r") {
+            s.run().await
+        } else if s.opts().opt_present("w") {
+            s.run_with(&status, |_| Client { cluster }).await
+        } else if s.opts().opt_present("a") {
+            s.run_with_async(cmd, |_| async move {
+                if cluster.is_empty() {
+                    anyhow::bail!("could not connect");
+                }
+                Ok(Client { cluster })
+            })
+            .await
+        } else {
+            s.run_with(cmd, |_| Client { cluster }).await
+        }
+    }
+
+    #[tokio::test]
+    async fn mapped_context() {
+        let res = invoke(do_cluster, &["top", "-n", "east", "status"]).await;
+        assert_eq!(res.stdout(), "status of east\n");
+
+        let res =
+            invoke(do_cluster, &["top", "-a", "-n", "west", "status"]).await;
+        assert_eq!(res.stdout(), "status of west\n");
+
+        let res = invoke(do_cluster, &["top", "-a", "status"]).await;
+        assert_eq!(
+            res.stderr(),
+            "status true\nERROR: top: could not connect\n"
+        );
+
+        let res = invoke(do_cluster, &["top", "-r", "status"]).await;
+        assert_eq!(
+            res.stderr(),
+            "status true\n\
+            ERROR: top status: command \"status\" must be run with \
+            Selection::run_with()\n"
+        );
+
+        let res = invoke(do_cluster, &["top", "-w", "nodes", "list"]).await;
+        assert_eq!(
+            res.stderr(),
+            "nodes true\n\
+            ERROR: top nodes: command \"nodes\" must be run with \
+            Selection::run_with()\n"
+        );
+
+        /*
+         * Commands that were not registered with cmd_with() are run as usual.
+         */
+        let res = invoke(do_cluster, &["top", "-n", "east", "ping"]).await;
+        assert_eq!(res.stdout(), "pong\n");
+        assert_eq!(res.stderr(), "ping false\n");
+
+        let res =
+            invoke(do_cluster, &["top", "-n", "east", "nodes", "list"]).await;
+        assert_eq!(res.stdout(), "nodes of east\n");
+    }
+
+    #[tokio::test]
+    async fn mapped_context_describe() {
+        let res = invoke(do_cluster, &["top", "help"]).await;
+        assert_eq!(
+            res.stdout(),
+            "Commands:\n    \
+            status              show status\n    \
+            nodes               manage nodes\n        \
+            list            list nodes\n    \
+            ping                check the cluster\n"
+        );
+
+        let res = invoke(do_cluster, &["top", "__complete", "nodes", ""]).await;
+        assert_eq!(res.stdout(), "list\nhelp\n");
+
+        let pages = crate::man::pages("top", (), do_cluster).await.unwrap();
+        assert!(pages.iter().any(|p| p.name() == "top-nodes-list"));
+
+        let v = crate::json::tree("top", (), do_cluster).await.unwrap();
+        assert_eq!(v["commands"][1]["level"]["commands"][0]["name"], "list");
+    }
+
+    async fn do_log_list(mut l: Level<()>) -> Result<()> {
+        no_args!(l);
+        let log = l.logger();
+        debug!(log, "listing"; "kind" => "box");
+        info!(log, "listed");
+        warn!(log, "nothing found");
+        Ok(())
+    }
+
+    async fn do_log_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("list", "list things", do_log_list)?;
+        l.standard_logging();
+        let s = sel!(l);
+        trace!(s.logger(), "selected");
+        s.run().await
+    }
+
+    #[tokio::test]
+    async fn standard_logging() {
+        let res = invoke(do_log_top, &["top", "list"]).await;
+        assert_eq!(res.stderr(), "INFO listed\nWARN nothing found\n");
+
+        let res = invoke(do_log_top, &["top", "-v", "list", "-v"]).await;
+        assert_eq!(
+            res.stderr(),
+            "DEBG listing kind=box\nINFO listed\nWARN nothing found\n"
+        );
+
+        let res = invoke(do_log_top, &["top", "-vv", "list", "-qqq"]).await;
+        assert_eq!(res.stderr(), "WARN nothing found\n");
+
+        let res =
+            invoke(do_log_top, &["top", "-q", "list", "--log-format=json"])
+                .await;
+        let v: serde_json::Value =
+            serde_json::from_str(res.stderr().trim()).unwrap();
+        assert_eq!(v["msg"], "nothing found");
+
+        let res = invoke(do_log_top, &["top", "--log-format", "xml"]).await;
+        assert_eq!(res.status(), 2);
+        assert!(res.stderr().contains("must be one of term, json"));
+    }
+
+    async fn do_hook_list(mut l: Level<()>) -> Result<()> {
+        no_args!(l);
+        writeln!(l.stdout(), "listed")?;
+        Ok(())
+    }
+
+    async fn do_hook_fail(mut l: Level<()>) -> Result<()> {
+        no_args!(l);
+        anyhow::bail!("connection refused");
+    }
+
+    async fn do_hook_thing(mut l: Level<()>) -> Result<()> {
+        l.cmd("list", "list things", do_hook_list)?;
+        l.cmd("fail", "fail to do things", do_hook_fail)?;
+        sel!(l).run().await
+    }
+
+    async fn do_hook_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("thing", "manage things", do_hook_thing)?;
+        l.cmd("secret", "keep secrets", do_hook_list)?;
+        l.global_optflag("n", "dry-run", "do not do anything");
+
+        let out = l.stdout();
+        l.around_run(move |d, next| {
+            let mut out = out.clone();
+            async move {
+                writeln!(out, "enter {}", d.names().join(" "))?;
+                let res = next.await;
+                writeln!(out, "leave {} ok={}", d.command(), res.is_ok())?;
+                res
+            }
+        });
+        l.before_run(|d| async move {
+            if d.command() == "secret" {
+                anyhow::bail!("not authorized");
+            }
+            Ok(())
+        });
+        let out = l.stdout();
+        l.before_run(move |d| {
+            let dry = d.globals().opt_present("dry-run");
+            let mut out = out.clone();
+            async move {
+                if dry {
+                    writeln!(out, "would run {}", d.desc())?;
+                    anyhow::bail!("dry run");
+                }
+                Ok(())
+            }
+        });
+        l.after_run(|_, res| async move {
+            res.map_err(|e| {
+                if e.to_string() == "connection refused" {
+                    anyhow::anyhow!("could not reach the server")
+                } else {
+                    e
+                }
+            })
+        });
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn hooks() {
+        let res = invoke(do_hook_top, &["top", "thing", "list"]).await;
+        assert!(res.success());
+        assert_eq!(
+            res.stdout(),
+            "enter top thing\n\
+            enter top thing list\n\
+            listed\n\
+            leave list ok=true\n\
+            leave thing ok=true\n"
+        );
+
+        let res = invoke(do_hook_top, &["top", "thing", "fail"]).await;
+        assert_eq!(
+            res.stderr(),
+            "ERROR: top thing fail: could not reach the server\n"
+        );
+        assert!(res.stdout().ends_with("leave thing ok=false\n"));
+
+        let res = invoke(do_hook_top, &["top", "secret"]).await;
+        assert_eq!(res.stderr(), "ERROR: top secret: not authorized\n");
+        assert_eq!(res.stdout(), "enter top secret\nleave secret ok=false\n");
+
+        let res = invoke(do_hook_top, &["top", "-n", "thing", "list"]).await;
+        assert_eq!(res.stderr(), "ERROR: top thing: dry run\n");
+        assert!(res.stdout().contains("would run manage things\n"));
+        assert!(!res.stdout().contains("listed"));
+
+        let res = invoke(do_hook_top, &["top", "help"]).await;
+        assert!(res.stdout().contains("    secret"));
+        assert!(!res.stdout().contains("enter"));
+    }
+
+    async fn do_err_list(mut l: Level<()>) -> Result<()> {
+        no_args!(l);
+        Err(anyhow::anyhow!("connection refused"))
+            .context("could not list things")
+            .exit_code(3)
+    }
+
+    async fn do_err_thing(mut l: Level<()>) -> Result<()> {
+        l.cmd("list", "list things", do_err_list)?;
+        l.cmd("nothing", "do nothing", |_| async { Ok(()) })?;
+        sel!(l).run().await
+    }
+
+    async fn do_err_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("thing", "manage things", do_err_thing)?;
+        l.debug_errors();
+        let s = sel!(l);
+        s.run().await?;
+        anyhow::bail!("could not save state");
+    }
+
+    async fn do_walk_late(mut l: Level<()>) -> Result<()> {
+        /*
+         * The context object is still available once the arguments have been
+         * parsed, even while describing the tree.
+         */
+        l.parse()?;
+        l.context();
+        Ok(())
+    }
+
+    async fn do_walk_broken(_: Level<()>) -> Result<()> {
+        anyhow::bail!("no credentials");
+    }
+
+    async fn do_walk_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("late", "use the context late", do_walk_late)?;
+        l.cmd("broken", "fail before parsing", do_walk_broken)?;
+        sel!(l).run().await
     }
 
-    pub fn optopt(
-        &mut self,
-        short_name: &str,
-        long_name: &str,
-        desc: &str,
-        hint: &str,
-    ) {
-        self.usage_opts = true;
-        self.options.optopt(short_name, long_name, desc, hint);
+    #[tokio::test]
+    async fn help_incomplete() {
+        let res = invoke(do_walk_top, &["top", "help"]).await;
+        assert_eq!(res.status(), 1);
+        assert_eq!(res.stdout(), "");
+        assert_eq!(
+            res.stderr(),
+            "ERROR: top: could not describe \"top broken\": no credentials\n"
+        );
+
+        let res = invoke(do_walk_top, &["top", "late"]).await;
+        assert!(res.success());
     }
 
-    pub fn reqopt(
-        &mut self,
-        short_name: &str,
-        long_name: &str,
-        desc: &str,
-        hint: &str,
-    ) {
-        if self.options_required.is_none() {
-            self.options_required = Some(Vec::new());
+    #[tokio::test]
+    async fn error_reporting() {
+        let res = invoke(do_err_top, &["top", "thing", "list"]).await;
+        assert_eq!(res.status(), 3);
+        assert_eq!(
+            res.stderr(),
+            "ERROR: top thing list: could not list things\n    \
+            caused by: connection refused\n"
+        );
+
+        let res =
+            invoke(do_err_top, &["top", "thing", "list", "--debug"]).await;
+        assert_eq!(res.status(), 3);
+        assert!(res.stderr().contains("\nbacktrace:"));
+
+        /*
+         * Once a command succeeds, errors are reported from the level above.
+         */
+        let res = invoke(do_err_top, &["top", "thing", "nothing"]).await;
+        assert_eq!(res.status(), 1);
+        assert_eq!(res.stderr(), "ERROR: top: could not save state\n");
+
+        /*
+         * A "debug" option of the consumer's own does not show a backtrace.
+         */
+        async fn do_own_debug(mut l: Level<()>) -> Result<()> {
+            l.cmd("thing", "manage things", do_err_thing)?;
+            l.global_optflag("", "debug", "debug the things");
+            sel!(l).run().await
         }
-        self.options_required.as_mut().unwrap().push(OptionPair {
-            short: short_name.to_string(),
-            long: long_name.to_string(),
-        });
-        self.usage_opts = true;
-        self.options.optopt(short_name, long_name, desc, hint);
+        let res =
+            invoke(do_own_debug, &["top", "--debug", "thing", "list"]).await;
+        assert_eq!(res.status(), 3);
+        assert!(!res.stderr().contains("backtrace"));
     }
 
-    pub fn mutually_exclusive(&mut self, pairs: &[(&str, &str)]) {
-        if self.options_mutex.is_none() {
-            self.options_mutex = Some(Vec::new());
+    #[test]
+    fn aliases() {
+        async fn nop(_: Level<()>) -> Result<()> {
+            Ok(())
         }
-        self.options_mutex.as_mut().unwrap().push(
-            pairs
-                .iter()
-                .map(|(short, long)| OptionPair {
-                    short: short.to_string(),
-                    long: long.to_string(),
-                })
-                .collect(),
+
+        let mut l = level(&["ls"]);
+        l.cmda("list", "ls", "list things", nop).unwrap();
+        l.alias("list", "l").unwrap();
+        l.halias("list", "enumerate").unwrap();
+        l.cmd("info", "get information", nop).unwrap();
+
+        assert!(l.cmd("list", "again", nop).is_err());
+        assert!(l.cmd("ls", "clash with alias", nop).is_err());
+        assert!(l.cmda("lint", "l", "clash with alias", nop).is_err());
+        assert!(l.alias("info", "list").is_err());
+        assert!(l.alias("info", "enumerate").is_err());
+        assert!(l.alias("bogus", "b").is_err());
+
+        assert!(l
+            .gen_usage()
+            .contains("    list (ls, l)        list things\n"));
+        assert!(!l.gen_usage().contains("enumerate"));
+
+        for want in ["list", "ls", "l", "enumerate"] {
+            assert!(matches!(l.find_command(want), Ok(Some(0))));
+        }
+    }
+
+    #[test]
+    fn usage_error_suggest_option() {
+        let mut l = level(&["--extnd"]);
+        l.optflag("x", "extend", "extend");
+
+        let e = l.parse().err().expect("parse should fail");
+        let ue = e.downcast_ref::<UsageError>().expect("usage error");
+        assert_eq!(
+            ue.message(),
+            "Unrecognized option: 'extnd'; did you mean \"--extend\"?"
         );
     }
 
-    /**
-     * If this command level is a terminal node, just parse arguments and the
-     * optional table.  This should be called via the `args()!` macro, or if the
-     * command does not expect any arguments, the `no_args()!` macro.
-     * Automatically handles `--help` and any table output formatting options.
-     */
-    pub fn parse(&mut self) -> Result<Option<Arguments>> {
-        let res = if let Some(args) = &self.args {
-            self.options.parse(args)
-        } else {
-            self.options.parse(std::env::args_os().skip(1))
-        };
+    #[test]
+    fn usage_error_suggest_column() {
+        let mut l = level(&["-o", "nmae,size"]);
+        l.add_column("name", 8, true);
+        l.add_column("size", 8, true);
 
-        match res {
-            Ok(res) => {
-                if res.opt_present("help") {
-                    self.usage();
-                    return Ok(None);
-                }
+        let e = l.parse().err().expect("parse should fail");
+        let ue = e.downcast_ref::<UsageError>().expect("usage error");
+        assert_eq!(
+            ue.message(),
+            "invalid column names: nmae (did you mean \"name\"?)"
+        );
+        assert!(ue.usage().contains("Columns:\n    name"));
+    }
 
-                /*
-                 * Ensure all required options are present.
-                 */
-                if let Some(reqopts) = &self.options_required {
-                    let mut missing = Vec::new();
-                    for op in reqopts.iter() {
-                        let oksh = op.has_short() && res.opt_present(&op.short);
-                        let oklo = op.has_long() && res.opt_present(&op.long);
+    #[test]
+    fn option_env() {
+        fn register(l: &mut Level<()>) {
+            l.reqopt("k", "kind", "kind of thing", "KIND");
+            l.option_env("kind", "HIERCMD_TEST_KIND").unwrap();
+            l.optopt("s", "", "size of thing", "SIZE");
+            l.option_env("s", "HIERCMD_TEST_SIZE").unwrap();
+            l.optopt("w", "weight", "weight of thing", "WEIGHT");
+            l.mutually_exclusive(&[("s", ""), ("w", "")]);
+            l.requires(("w", ""), ("k", ""));
+        }
 
-                        if !oksh && !oklo {
-                            missing.push(op.to_string());
-                        }
-                    }
+        assert!(level(&[]).option_env("bogus", "X").is_err());
+        assert!(level(&[]).option_env("help", "X").is_err());
 
-                    if !missing.is_empty() {
-                        bad_args!(
-                            self,
-                            "required options missing: {}",
-                            missing.join(", ")
-                        );
-                    }
-                }
+        for env in [&[][..], &[("HIERCMD_TEST_KIND", "")][..]] {
+            assert_eq!(
+                usage_message(&[], with_env(env, register)),
+                "required options missing: -k (--kind)"
+            );
+        }
+        assert!(level_with(&[], register)
+            .gen_usage()
+            .contains("kind of thing (env: HIERCMD_TEST_KIND)\n"));
 
-                /*
-                 * Ensure there are no conflicts between mutually exclusive
-                 * options.
-                 */
-                if let Some(mutopts) = &self.options_mutex {
-                    for opts in mutopts.iter() {
-                        let conflicts = opts
-                            .iter()
-                            .filter(|opt| {
-                                (!opt.short.is_empty()
-                                    && res.opt_present(&opt.short))
-                                    || (!opt.long.is_empty()
-                                        && res.opt_present(&opt.long))
-                            })
-                            .map(|s| s.to_string())
-                            .collect::<Vec<_>>();
-                        if conflicts.len() > 1 {
-                            bad_args!(
-                                self,
-                                "{} are mutually exclusive",
-                                conflicts.join(" and "),
-                            );
-                        }
-                    }
-                }
+        let env =
+            [("HIERCMD_TEST_KIND", "box"), ("HIERCMD_TEST_SIZE", "large")];
+        let mut l = level_with(&["arg"], with_env(&env, register));
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.opts().opt_str("kind").as_deref(), Some("box"));
+        assert_eq!(a.opts().opt_str("s").as_deref(), Some("large"));
+        assert_eq!(a.args(), &["arg"]);
 
-                let table = if let Some(mut table) = self.table.take() {
-                    table
-                        .output_from_list(res.opt_str("o").as_deref())
-                        .sort_from_list_asc(res.opt_str("s").as_deref())
-                        .sort_from_list_desc(res.opt_str("S").as_deref())
-                        .show_header(!res.opt_present("H"))
-                        .tab_separated(res.opt_present("H"))
-                        .parseable(res.opt_present("p"));
+        let mut l = level_with(&["-k", "crate"], with_env(&env, register));
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.opts().opt_str("kind").as_deref(), Some("crate"));
 
-                    if !self.lazy_columns {
-                        let mcn = table.missing_column_names();
-                        if !mcn.is_empty() {
-                            bad_args!(
-                                self,
-                                "invalid column names: {}",
-                                mcn.join(", ")
-                            );
-                        }
-                    }
+        /*
+         * A value from the environment neither conflicts with an option the
+         * user provided, nor overrides their choice.
+         */
+        let mut l = level_with(&["-w", "3"], with_env(&env, register));
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.opts().opt_str("s"), None);
+        assert_eq!(a.opts().opt_str("w").as_deref(), Some("3"));
 
-                    Some(table)
-                } else {
-                    None
-                };
+        assert_eq!(
+            usage_message(
+                &["-w", "3", "-s", "small"],
+                with_env(&env, register)
+            ),
+            "-s and -w are mutually exclusive"
+        );
+    }
 
-                Ok(Some(Arguments {
-                    matches: res,
-                    table,
-                }))
-            }
-            Err(e) => {
-                bad_args!(self, "{}", e);
-            }
-        }
+    fn config_path() -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("hiercmd-test-{}.toml", std::process::id()))
     }
 
-    /**
-     * Parse options for this command level and select the next command.  The
-     * best way to call this routine is using the `sel!()` macro, which handles
-     * the early return and exit-on-failure conditions automatically.
-     */
-    pub fn select(mut self) -> Result<Option<Selection<C>>> {
-        if self.commands.is_empty() {
-            bail!("no commands provided by consumer");
+    async fn do_config_list(mut l: Level<()>) -> Result<()> {
+        l.add_column("name", 8, true);
+        l.add_column("size", 8, true);
+        l.optflag("a", "all", "all things");
+        l.optmulti("t", "tag", "tag to match", "TAG");
+        let a = args!(l);
+        writeln!(
+            l.stdout(),
+            "{:?} {} {:?}",
+            a.opts().opt_str("o"),
+            a.opts().opt_present("all"),
+            a.opts().opt_strs("tag")
+        )?;
+        Ok(())
+    }
+
+    async fn do_config_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("list", "list things", cmd!(do_config_list))?;
+        l.config_file(Path::new("/nonexistent"));
+        l.config_file(&config_path());
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn config_file() {
+        let path = config_path();
+        std::fs::write(
+            &path,
+            "[list]\no = \"size\"\nall = true\ntag = [\"x\", \"y\"]\n",
+        )
+        .unwrap();
+
+        let res = invoke(cmd!(do_config_top), &["top", "list"]).await;
+        assert_eq!(res.stdout(), "Some(\"size\") true [\"x\", \"y\"]\n");
+
+        let res = invoke(
+            cmd!(do_config_top),
+            &["top", "list", "-o", "name", "-t", "z"],
+        )
+        .await;
+        assert_eq!(res.stdout(), "Some(\"name\") true [\"z\"]\n");
+
+        for args in [
+            ["top", "--no-config", "list"],
+            ["top", "list", "--no-config"],
+        ] {
+            let res = invoke(cmd!(do_config_top), &args).await;
+            assert_eq!(res.stdout(), "None false []\n");
         }
 
-        let args = args!(self);
+        let res = invoke(cmd!(do_config_top), &["top", "--help"]).await;
+        assert_eq!(res.stdout().matches("--no-config").count(), 1);
+
+        std::fs::write(&path, "[list]\nbogus = 1\n").unwrap();
+        let res = invoke(cmd!(do_config_top), &["top", "list"]).await;
+        assert_eq!(res.status(), 1);
+        assert!(res
+            .stderr()
+            .ends_with(": unknown option \"bogus\" for \"top list\"\n"));
 
         /*
-         * Determine which command the user is trying to run.
+         * Problems with the file do not prevent the user getting help.
          */
-        if args.matches.free.is_empty() {
-            bad_args!(self, "choose a command");
+        for body in ["[list]\nbogus = 1\n", "[list\n"] {
+            std::fs::write(&path, body).unwrap();
+            let res =
+                invoke(cmd!(do_config_top), &["top", "list", "--help"]).await;
+            assert!(res.success());
+            assert!(res.stdout().starts_with("Usage: top list [OPTS]"));
         }
 
-        let usage = self.gen_usage();
-
-        let want = args.matches.free[0].as_str();
-        for command in self.commands {
-            if command.name != want {
-                if let Some(alias) = &command.alias {
-                    if alias.as_str() != want {
-                        continue;
-                    }
-                } else {
-                    continue;
-                }
-            }
+        std::fs::write(&path, "[list]\nall = \"yes\"\n").unwrap();
+        let res = invoke(cmd!(do_config_top), &["top", "list"]).await;
+        assert!(res
+            .stderr()
+            .ends_with(": option --all is a flag; use true or false\n"));
 
-            return Ok(Some(Selection {
-                names: self.names,
-                private: self.private,
-                command,
-                matches: args.matches,
-            }));
-        }
+        std::fs::remove_file(&path).unwrap();
+        let res = invoke(cmd!(do_config_top), &["top", "list"]).await;
+        assert_eq!(res.stdout(), "None false []\n");
+    }
 
-        print!("{}", usage);
-        bail!("command \"{}\" not understood", &args.matches.free[0]);
+    fn alias_config_path() -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("hiercmd-test-alias-{}.toml", std::process::id()))
     }
 
-    pub fn usage(&self) {
-        print!("{}", self.gen_usage());
+    async fn do_alias_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("list", "list things", cmd!(do_config_list))?;
+        l.config_file(&alias_config_path());
+        sel!(l).run().await
     }
 
-    pub fn usage_error(&self, msg: &str) {
-        eprint!("{}", self.gen_usage());
-        eprintln!("ERROR: {}", msg);
+    async fn do_alias_cmd_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("alias", "manage aliases", cmd!(do_config_list))?;
+        l.config_file(&alias_config_path());
+        sel!(l).run().await
     }
 
-    fn gen_usage(&self) -> String {
-        let mut out = "Usage:".to_string();
+    #[tokio::test]
+    async fn config_aliases() {
+        let path = alias_config_path();
+        std::fs::write(
+            &path,
+            "[alias]\n\
+            ls = \"list -o name --tag 'a b'\"\n\
+            la = \"ls --all\"\n",
+        )
+        .unwrap();
+
+        let res = invoke(cmd!(do_alias_top), &["top", "la", "-t", "c"]).await;
+        assert_eq!(res.stdout(), "Some(\"name\") true [\"a b\", \"c\"]\n");
+
+        let res = invoke(cmd!(do_alias_top), &["top", "--help"]).await;
+        assert!(res.stdout().contains(
+            "Aliases:\n    \
+            ls                  list -o name --tag a b\n    \
+            la                  ls --all\n"
+        ));
+
+        std::fs::write(&path, "[alias]\na = \"b\"\nb = \"a -x\"\n").unwrap();
+        let res = invoke(cmd!(do_alias_top), &["top", "a"]).await;
+        assert_eq!(res.status(), 1);
+        assert_eq!(
+            res.stderr(),
+            "ERROR: top: alias \"a\" is recursive: a -> b -> a\n"
+        );
+
+        std::fs::write(
+            &path,
+            "[alias]\nlist = \"list -a\"\nla = \"list -a\"\n",
+        )
+        .unwrap();
+        let res = invoke(cmd!(do_alias_top), &["top", "list"]).await;
+        assert_eq!(res.status(), 1);
+        assert!(res.stderr().ends_with(
+            ": alias \"list\" conflicts with a command of the same name\n"
+        ));
+
         /*
-         * The usage synopsis starts with the first level (the command name) and
-         * then includes each level down to the present level:
+         * The conflict does not get in the way of help or other commands.
          */
-        for n in self.names.iter() {
-            out.push_str(&format!(" {}", n));
-        }
-        if self.usage_opts {
-            /*
-             * If this level specifies any options, mention that in the usage
-             * synposis.
-             */
-            out.push_str(" [OPTS]");
-        }
-        if !self.commands.is_empty() {
-            /*
-             * If this is level is not terminal (i.e., it has further
-             * sub-levels) then include that in the synopsis:
-             */
-            out.push_str(" COMMAND");
-        }
-        //out.push_str(" [OPTS]");
-        if let Some(usage_args) = &self.usage_args {
-            out.push_str(&format!(" {}", usage_args));
-        }
-        out.push_str("\n");
-        if !self.commands.is_empty() {
-            out.push_str("\nCommands:\n");
-            for cmd in self.commands.iter() {
-                if !cmd.visible {
-                    continue;
-                }
-                let cn = if let Some(alias) = &cmd.alias {
-                    format!("{} ({})", cmd.name, alias)
-                } else {
-                    cmd.name.to_string()
-                };
-                out.push_str(&format!("    {:<19} {}\n", cn, cmd.desc));
-            }
-        }
-        let mut out = self.options.usage(&out);
-        out.push('\n');
-        if let Some(table) = &self.table {
-            let cols = table.column_names();
-            if !cols.is_empty() {
-                out.push_str("Columns:\n");
-                for col in cols.iter() {
-                    out.push_str(&format!("    {:<19}\n", col));
-                }
-            }
-            out.push('\n');
-        }
-        out
-    }
-}
+        let res = invoke(cmd!(do_alias_top), &["top", "--help"]).await;
+        assert!(res.success());
+        let res = invoke(cmd!(do_alias_top), &["top", "list", "--help"]).await;
+        assert!(res.success());
+        assert!(res.stdout().starts_with("Usage: top list"));
+        let res = invoke(cmd!(do_alias_top), &["top", "help", "list"]).await;
+        assert!(res.success());
+        let res = invoke(cmd!(do_alias_top), &["top", "la"]).await;
+        assert_eq!(res.stdout(), "None true []\n");
 
-pub struct Selection<C: LevelContext> {
-    private: C,
-    names: Vec<String>,
-    command: CommandInfo<C>,
-    matches: getopts::Matches,
-}
+        /*
+         * A command named "alias" gets its options from the "alias" table.
+         */
+        std::fs::write(&path, "[alias]\nall = true\n").unwrap();
+        let res = invoke(cmd!(do_alias_cmd_top), &["top", "alias"]).await;
+        assert_eq!(res.stdout(), "None true []\n");
 
-impl<C: LevelContext> Selection<C> {
-    pub fn opts(&self) -> &getopts::Matches {
-        &self.matches
+        std::fs::remove_file(&path).unwrap();
     }
 
-    pub async fn run(self) -> Result<()> {
-        let mut names = self.names.clone();
-        names.push(self.command.name.clone());
-        let l = Level::new_sub(
-            names,
-            self.private,
-            Some(self.matches.free[1..].to_vec()),
+    #[test]
+    fn option_constraints() {
+        fn register(l: &mut Level<()>) {
+            l.optopt("k", "key", "key file", "FILE");
+            l.optopt("", "cert", "certificate file", "FILE");
+            l.optflag("a", "", "first");
+            l.optflag("b", "", "second");
+            l.optflag("x", "", "third");
+            l.optflag("y", "", "fourth");
+            l.requires(("k", "key"), ("", "cert"));
+            l.at_least_one_of(&[("a", ""), ("b", "")]);
+            l.exactly_one_of(&[("x", ""), ("y", "")]);
+        }
+
+        assert!(level_with(&["-a", "-x"], register).parse().is_ok());
+        assert!(level_with(&["-abx", "-k", "k", "--cert", "c"], register)
+            .parse()
+            .is_ok());
+        assert_eq!(
+            usage_message(&["-a", "-x", "--key", "k"], register),
+            "-k (--key) requires --cert"
+        );
+        assert_eq!(
+            usage_message(&["-x"], register),
+            "at least one of -a, -b is required"
+        );
+        assert_eq!(
+            usage_message(&["-a"], register),
+            "exactly one of -x, -y is required"
+        );
+        assert_eq!(
+            usage_message(&["-axy"], register),
+            "-x and -y are mutually exclusive"
         );
-        (self.command.func)(l).await
-    }
 
-    pub fn context(&self) -> &C {
-        &self.private
-    }
+        assert!(level_with(&[], register).gen_usage().contains(
+            "Constraints:\n    \
+            -k (--key) requires --cert\n    \
+            at least one of -a, -b is required\n    \
+            exactly one of -x, -y is required\n"
+        ));
 
-    pub fn context_mut(&mut self) -> &mut C {
-        &mut self.private
-    }
-}
+        /*
+         * Values from the environment are subject to the same constraints,
+         * unless the user provided another option from the group.
+         */
+        fn register_env(l: &mut Level<()>) {
+            l.optopt("k", "key", "key file", "FILE");
+            l.option_env("key", "HIERCMD_TEST_KEY").unwrap();
+            l.optopt("", "cert", "certificate file", "FILE");
+            l.optopt("x", "", "third", "X");
+            l.option_env("x", "HIERCMD_TEST_X").unwrap();
+            l.optopt("y", "", "fourth", "Y");
+            l.option_env("y", "HIERCMD_TEST_Y").unwrap();
+            l.requires(("k", "key"), ("", "cert"));
+            l.exactly_one_of(&[("x", ""), ("y", "")]);
+        }
 
-pub struct Arguments {
-    matches: getopts::Matches,
-    table: Option<table::TableBuilder>,
-}
+        let env = [("HIERCMD_TEST_KEY", "k"), ("HIERCMD_TEST_X", "x")];
+        assert_eq!(
+            usage_message(&[], with_env(&env, register_env)),
+            "-k (--key) requires --cert"
+        );
+        assert!(level_with(&["--cert", "c"], with_env(&env, register_env))
+            .parse()
+            .is_ok());
 
-impl Arguments {
-    pub fn opts(&self) -> &getopts::Matches {
-        &self.matches
+        let env = [("HIERCMD_TEST_X", "x"), ("HIERCMD_TEST_Y", "y")];
+        assert_eq!(
+            usage_message(&[], with_env(&env, register_env)),
+            "-x and -y are mutually exclusive"
+        );
+        let mut l = level_with(&["-y", "z"], with_env(&env, register_env));
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.opts().opt_str("x"), None);
+        assert_eq!(a.opts().opt_str("y").as_deref(), Some("z"));
     }
 
-    pub fn args(&self) -> &[String] {
-        &self.matches.free
+    #[test]
+    fn option_choices() {
+        fn register(l: &mut Level<()>) {
+            l.optchoice(
+                "",
+                "state",
+                "state of things",
+                &["running", "stopped"],
+            );
+        }
+
+        let mut l = level_with(&["--state", "stopped"], register);
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.opts().opt_str("state").as_deref(), Some("stopped"));
+
+        assert_eq!(
+            usage_message(&["--state", "runing"], register),
+            "invalid value \"runing\" for option --state: must be one of \
+            running, stopped; did you mean \"running\"?"
+        );
+        assert!(level_with(&[], register).gen_usage().contains(
+            "--state running|stopped\n                        \
+            state of things\n"
+        ));
     }
 
-    pub fn add_column(&mut self, name: &str, width: usize, def: bool) {
-        self.table.as_mut().unwrap().add_column(name, width, def);
+    async fn do_global_leaf(mut l: Level<()>) -> Result<()> {
+        let a = args!(l);
+        let g = a.globals();
+        writeln!(
+            l.stdout(),
+            "{} {:?} {:?}",
+            g.opt_count("v"),
+            g.opt_str("profile"),
+            a.args()
+        )?;
+        Ok(())
     }
 
-    pub fn set_column_default(&mut self, name: &str, def: bool) {
-        self.table.as_mut().unwrap().set_column_default(name, def);
+    async fn do_global_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("leaf", "leaf command", cmd!(do_global_leaf))?;
+        l.global_optflagmulti("v", "", "verbose");
+        l.global_optopt("", "profile", "profile to use", "NAME");
+        sel!(l).run().await
     }
 
-    pub fn table(&self) -> table::Table {
-        self.table.as_ref().unwrap().build()
+    #[tokio::test]
+    async fn global_options() {
+        let res = invoke(
+            cmd!(do_global_top),
+            &[
+                "top",
+                "-v",
+                "--profile",
+                "a",
+                "leaf",
+                "-v",
+                "--profile",
+                "b",
+            ],
+        )
+        .await;
+        assert_eq!(res.stdout(), "2 Some(\"b\") []\n");
+
+        let res =
+            invoke(cmd!(do_global_top), &["top", "leaf", "-v", "x"]).await;
+        assert_eq!(res.stdout(), "1 None [\"x\"]\n");
+
+        let res = invoke(cmd!(do_global_top), &["top", "leaf", "--help"]).await;
+        assert!(res.success());
+        assert!(res
+            .stdout()
+            .starts_with("Usage: top leaf [OPTS] [ARGS...]\n"));
+        assert!(res.stdout().contains("\nGlobal options:\n    -v"));
+        assert!(!res.stdout().contains("Options:\n    -v"));
     }
 }
@@ -0,0 +1,240 @@
+/*!
+ * The standard logging options, enabled with `Level::standard_logging()`.
+ * The verbosity is raised by each use of `-v` and lowered by each use of
+ * `-q`, at any level, and log records are written to the stderr of the level
+ * in either a terse human-readable form or as one JSON object per line.
+ */
+
+use super::{Globals, Output};
+use serde_json::Value;
+use slog::{Drain, Key, OwnedKVList, Record, Serializer, KV};
+use std::io::Write;
+
+pub(crate) const VERBOSE: &str = "verbose";
+pub(crate) const QUIET: &str = "quiet";
+pub(crate) const LOG_FORMAT: &str = "log-format";
+pub(crate) const FORMATS: &[&str] = &["term", "json"];
+
+/**
+ * Collects the key-value pairs of a log record, and of the logger it was
+ * written to.  Numbers and booleans keep their type, so that they are not
+ * quoted in JSON output; anything else is formatted as a string.
+ */
+#[derive(Default)]
+struct Pairs(Vec<(String, Value)>);
+
+impl Serializer for Pairs {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &std::fmt::Arguments<'_>,
+    ) -> slog::Result {
+        self.push(key, val.to_string())
+    }
+
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.push(key, val)
+    }
+
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        self.emit_f64(key, val.into())
+    }
+
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        /*
+         * JSON has no representation for infinities or NaN.
+         */
+        match serde_json::Number::from_f64(val) {
+            Some(n) => self.push(key, n),
+            None => self.push(key, val.to_string()),
+        }
+    }
+}
+
+impl Pairs {
+    fn push<V: Into<Value>>(&mut self, key: Key, val: V) -> slog::Result {
+        self.0.push((key.to_string(), val.into()));
+        Ok(())
+    }
+
+    fn collect(record: &Record<'_>, values: &OwnedKVList) -> Pairs {
+        /*
+         * Serializing into a Vec cannot fail.
+         */
+        let mut pairs = Pairs::default();
+        record.kv().serialize(record, &mut pairs).ok();
+        values.serialize(record, &mut pairs).ok();
+        pairs
+    }
+}
+
+struct TermDrain {
+    out: Output,
+}
+
+impl Drain for TermDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record<'_>,
+        values: &OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        let mut line =
+            format!("{} {}", record.level().as_short_str(), record.msg());
+        for (k, v) in Pairs::collect(record, values).0 {
+            match v {
+                Value::String(s) => line.push_str(&format!(" {}={}", k, s)),
+                v => line.push_str(&format!(" {}={}", k, v)),
+            }
+        }
+        writeln!(self.out.clone(), "{}", line).ok();
+        Ok(())
+    }
+}
+
+struct JsonDrain {
+    out: Output,
+}
+
+impl Drain for JsonDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &Record<'_>,
+        values: &OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        let mut obj = serde_json::Map::new();
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        obj.insert("time".into(), time.into());
+        obj.insert("level".into(), record.level().as_str().into());
+        obj.insert("msg".into(), record.msg().to_string().into());
+        for (k, v) in Pairs::collect(record, values).0 {
+            obj.entry(k).or_insert(v);
+        }
+        writeln!(self.out.clone(), "{}", Value::Object(obj)).ok();
+        Ok(())
+    }
+}
+
+/**
+ * Determine the log level from the number of times the user asked for more
+ * or less verbose output.  Informational messages are shown by default.
+ */
+fn level(verbose: usize, quiet: usize) -> slog::Level {
+    match verbose as isize - quiet as isize {
+        n if n <= -3 => slog::Level::Critical,
+        -2 => slog::Level::Error,
+        -1 => slog::Level::Warning,
+        0 => slog::Level::Info,
+        1 => slog::Level::Debug,
+        _ => slog::Level::Trace,
+    }
+}
+
+/**
+ * Construct a logger from the standard logging options provided at the
+ * current level and above, writing to `out`.
+ */
+pub(crate) fn logger(globals: &Globals, out: Output) -> slog::Logger {
+    let level = level(globals.opt_count(VERBOSE), globals.opt_count(QUIET));
+    match globals.opt_str(LOG_FORMAT).as_deref() {
+        Some("json") => slog::Logger::root(
+            JsonDrain { out }.filter_level(level).ignore_res(),
+            slog::o!(),
+        ),
+        _ => slog::Logger::root(
+            TermDrain { out }.filter_level(level).ignore_res(),
+            slog::o!(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{level, JsonDrain, TermDrain};
+    use crate::Output;
+    use slog::{debug, info, o, Drain, Logger};
+
+    #[test]
+    fn log_levels() {
+        assert_eq!(level(0, 0), slog::Level::Info);
+        assert_eq!(level(1, 0), slog::Level::Debug);
+        assert_eq!(level(3, 0), slog::Level::Trace);
+        assert_eq!(level(1, 2), slog::Level::Warning);
+        assert_eq!(level(0, 5), slog::Level::Critical);
+    }
+
+    #[test]
+    fn log_formats() {
+        let out = Output::capture();
+        let drain = TermDrain { out: out.clone() };
+        let log = Logger::root(
+            drain.filter_level(slog::Level::Info).ignore_res(),
+            o!("cmd" => "list"),
+        );
+        info!(log, "listing things"; "count" => 3);
+        debug!(log, "not shown");
+        assert_eq!(out.captured(), "INFO listing things count=3 cmd=list\n");
+
+        let out = Output::capture();
+        let log = Logger::root(JsonDrain { out: out.clone() }, o!());
+        info!(log, "listing \"things\"";
+            "count" => 3, "all" => true, "ratio" => 0.5, "kind" => "box");
+        let v: serde_json::Value =
+            serde_json::from_str(&out.captured()).unwrap();
+        assert_eq!(v["level"], "INFO");
+        assert_eq!(v["msg"], "listing \"things\"");
+        assert_eq!(v["count"], 3);
+        assert_eq!(v["all"], true);
+        assert_eq!(v["ratio"], 0.5);
+        assert_eq!(v["kind"], "box");
+        assert!(v["time"].is_f64());
+    }
+}
@@ -0,0 +1,367 @@
+/*!
+ * Generation of manual pages, in roff format, from the command tree.  The
+ * level handlers are walked in the same way as for the built-in help command,
+ * so the pages include every visible command along with its options and any
+ * table columns.
+ */
+
+use super::describe::{walk, LevelInfo};
+use super::{caller, Level, LevelContext};
+use anyhow::Result;
+use std::future::Future;
+
+const SECTION: &str = "1";
+
+/**
+ * A manual page for one level of the command tree.
+ */
+pub struct Page {
+    name: String,
+    content: String,
+}
+
+impl Page {
+    /**
+     * The name of the page, which is the names of each level joined by
+     * hyphens; e.g., "trial-thing-list".
+     */
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /**
+     * The conventional file name for the page; e.g., "trial-thing-list.1".
+     */
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.name, SECTION)
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+}
+
+/**
+ * Produce a manual page for each level in the command tree below the
+ * top-level handler `func`, where `name` is the command name and `private` is
+ * the context object to pass to the handlers.  This may be used from a build
+ * script, or from a hidden command; e.g.,
+ *
+ * ```ignore
+ * for page in hiercmd::man::pages("trial", (), cmd!(do_trial)).await? {
+ *     std::fs::write(page.file_name(), page.content())?;
+ * }
+ * ```
+ *
+ * If a handler fails, or does not parse its arguments, the tree cannot be
+ * walked in full and an error is returned instead.
+ */
+pub async fn pages<C, F, Fut>(
+    name: &str,
+    private: C,
+    func: F,
+) -> Result<Vec<Page>>
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let levels = walk(name, private, caller(func), false).await?;
+    Ok(levels.iter().map(|info| page(&levels, info)).collect())
+}
+
+/**
+ * Produce a single manual page that describes the whole command tree.
+ * Otherwise identical to `pages()`.
+ */
+pub async fn combined<C, F, Fut>(
+    name: &str,
+    private: C,
+    func: F,
+) -> Result<String>
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let levels = walk(name, private, caller(func), false).await?;
+
+    let mut out = String::new();
+    let top = if let Some(top) = levels.first() {
+        top
+    } else {
+        return Ok(out);
+    };
+
+    out.push_str(&format!(
+        ".TH {} {}\n",
+        escape(&name.to_uppercase()),
+        SECTION
+    ));
+    out.push_str(&format!(".SH NAME\n{}\n", escape(name)));
+    out.push_str(".SH SYNOPSIS\n");
+    synopsis(top, &mut out);
+    body(top, false, &mut out);
+
+    if levels.len() > 1 {
+        out.push_str(".SH \"COMMAND REFERENCE\"\n");
+        for info in levels.iter().skip(1) {
+            out.push_str(&format!(
+                ".SS \"{}\"\n",
+                escape(&info.names.join(" "))
+            ));
+            if let Some(desc) = info.desc(&levels) {
+                out.push_str(&format!("{}\n", escape(desc)));
+            }
+            out.push_str(".PP\n");
+            synopsis(info, &mut out);
+            body(info, true, &mut out);
+        }
+    }
+
+    Ok(out)
+}
+
+fn page(levels: &[LevelInfo], info: &LevelInfo) -> Page {
+    let name = info.names.join("-");
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        ".TH {} {}\n",
+        escape(&name.to_uppercase()),
+        SECTION
+    ));
+    out.push_str(".SH NAME\n");
+    if let Some(desc) = info.desc(levels) {
+        out.push_str(&format!("{} \\- {}\n", escape(&name), escape(desc)));
+    } else {
+        out.push_str(&format!("{}\n", escape(&name)));
+    }
+    out.push_str(".SH SYNOPSIS\n");
+    synopsis(info, &mut out);
+    body(info, false, &mut out);
+
+    /*
+     * Refer to the page for the level above, and to the pages for each
+     * sub-command of this level.
+     */
+    let mut see = Vec::new();
+    if let Some((_, parent)) = info.names.split_last() {
+        if !parent.is_empty() {
+            see.push(parent.join("-"));
+        }
+    }
+    for cmd in info.commands.iter().filter(|c| c.visible) {
+        let mut names = info.names.clone();
+        names.push(cmd.name.to_string());
+        if levels.iter().any(|l| l.names == names) {
+            see.push(names.join("-"));
+        }
+    }
+    if !see.is_empty() {
+        out.push_str(".SH \"SEE ALSO\"\n");
+        let refs = see
+            .iter()
+            .map(|n| format!(".BR {} ({})", escape(n), SECTION))
+            .collect::<Vec<_>>();
+        out.push_str(&refs.join(",\n"));
+        out.push('\n');
+    }
+
+    Page { name, content: out }
+}
+
+fn synopsis(info: &LevelInfo, out: &mut String) {
+    let names = info.names.join(" ");
+    out.push_str(&format!(".B {}\n", escape(&names)));
+    let rest = info.synopsis[names.len()..].trim();
+    if !rest.is_empty() {
+        out.push_str(&format!("{}\n", escape(rest)));
+    }
+}
+
+/**
+ * Render the commands, options, and columns for a level.  If `sub` is true,
+ * the level is being rendered within a larger page and each part gets a
+ * paragraph heading rather than a section of its own.
+ */
+fn body(info: &LevelInfo, sub: bool, out: &mut String) {
+    let heading = |out: &mut String, title: &str| {
+        if sub {
+            out.push_str(&format!(".PP\n.I {}\n", title));
+        } else {
+            out.push_str(&format!(".SH {}\n", title.to_uppercase()));
+        }
+    };
+
+    if info.commands.iter().any(|c| c.visible) {
+        heading(out, "Commands");
+        for cmd in info.commands.iter().filter(|c| c.visible) {
+            let mut tag = format!("\\fB{}\\fR", escape(&cmd.name));
+            if !cmd.aliases.is_empty() {
+                let aliases = cmd
+                    .aliases
+                    .iter()
+                    .map(|a| format!("\\fB{}\\fR", escape(a)))
+                    .collect::<Vec<_>>();
+                tag.push_str(&format!(" ({})", aliases.join(", ")));
+            }
+            out.push_str(&format!(".TP\n{}\n{}\n", tag, escape(&cmd.desc)));
+        }
+    }
+
+    /*
+     * Inherited global options are described on the page for the level that
+     * declared them.
+     */
+    if info.options.iter().any(|o| !o.inherited) {
+        heading(out, "Options");
+        for opt in info.options.iter().filter(|o| !o.inherited) {
+            let mut names = Vec::new();
+            if !opt.short.is_empty() {
+                names.push(format!("\\fB\\-{}\\fR", escape(&opt.short)));
+            }
+            if !opt.long.is_empty() {
+                names.push(format!("\\fB\\-\\-{}\\fR", escape(&opt.long)));
+            }
+            let mut tag = names.join(", ");
+            if opt.hasarg {
+                tag.push_str(&format!(" \\fI{}\\fR", escape(&opt.hint)));
+            }
+            let mut desc = escape(&opt.desc);
+            if opt.required {
+                desc.push_str(" (required)");
+            }
+            if let Some(env) = &opt.env {
+                desc.push_str(&format!(" (env: {})", escape(env)));
+            }
+            out.push_str(&format!(".TP\n{}\n{}\n", tag, desc));
+        }
+    }
+
+    if !info.columns.is_empty() {
+        heading(out, "Columns");
+        out.push_str(".RS\n");
+        for col in info.columns.iter() {
+            out.push_str(&format!("{}\n.br\n", escape(&col.name)));
+        }
+        out.push_str(".RE\n");
+    }
+}
+
+/**
+ * Escape text so that roff will render it literally.
+ */
+fn escape(s: &str) -> String {
+    let s = s.replace('\\', "\\e").replace('-', "\\-");
+    if s.starts_with('.') || s.starts_with('\'') {
+        format!("\\&{}", s)
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combined, pages};
+    use crate::prelude::*;
+    use anyhow::Result;
+
+    async fn do_list(mut l: Level<()>) -> Result<()> {
+        l.add_column("name", 8, true);
+        l.reqopt("k", "kind", "kind of thing", "KIND");
+        no_args!(l);
+        Ok(())
+    }
+
+    async fn do_thing(mut l: Level<()>) -> Result<()> {
+        l.cmda("list", "ls", "list things", cmd!(do_list))?;
+        l.hcmd("secret", "hidden command", cmd!(do_list))?;
+        sel!(l).run().await
+    }
+
+    async fn do_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("thing", "manage things", cmd!(do_thing))?;
+        l.optflag("x", "", "extend");
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn man_pages() {
+        let pages = pages("top", (), cmd!(do_top)).await.unwrap();
+        let names = pages.iter().map(|p| p.file_name()).collect::<Vec<_>>();
+        assert_eq!(names, ["top.1", "top-thing.1", "top-thing-list.1"]);
+
+        assert_eq!(
+            pages[2].content(),
+            ".TH TOP\\-THING\\-LIST 1\n\
+            .SH NAME\n\
+            top\\-thing\\-list \\- list things\n\
+            .SH SYNOPSIS\n\
+            .B top thing list\n\
+            [OPTS]\n\
+            .SH OPTIONS\n\
+            .TP\n\
+            \\fB\\-\\-help\\fR\n\
+            usage information\n\
+            .TP\n\
+            \\fB\\-s\\fR \\fICOLUMNS\\fR\n\
+            sort by column list (asc)\n\
+            .TP\n\
+            \\fB\\-S\\fR \\fICOLUMNS\\fR\n\
+            sort by column list (desc)\n\
+            .TP\n\
+            \\fB\\-o\\fR \\fICOLUMNS\\fR\n\
+            output column list\n\
+            .TP\n\
+            \\fB\\-H\\fR\n\
+            no header\n\
+            .TP\n\
+            \\fB\\-p\\fR\n\
+            print numbers in parseable (exact) format\n\
+            .TP\n\
+            \\fB\\-k\\fR, \\fB\\-\\-kind\\fR \\fIKIND\\fR\n\
+            kind of thing (required)\n\
+            .SH COLUMNS\n\
+            .RS\n\
+            name\n\
+            .br\n\
+            .RE\n\
+            .SH \"SEE ALSO\"\n\
+            .BR top\\-thing (1)\n"
+        );
+        assert!(pages[1]
+            .content()
+            .contains(".TP\n\\fBlist\\fR (\\fBls\\fR)\n"));
+        assert!(!pages[1].content().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn man_combined() {
+        let page = combined("top", (), cmd!(do_top)).await.unwrap();
+        assert!(page.starts_with(".TH TOP 1\n.SH NAME\ntop\n"));
+        assert!(page.contains(".SS \"top thing list\"\nlist things\n.PP\n"));
+    }
+
+    async fn do_broken(_: Level<()>) -> Result<()> {
+        anyhow::bail!("no connection")
+    }
+
+    async fn do_broken_top(mut l: Level<()>) -> Result<()> {
+        l.cmd("thing", "manage things", cmd!(do_thing))?;
+        l.cmd("broken", "fail to describe", cmd!(do_broken))?;
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn man_incomplete() {
+        let msg = "could not describe \"top broken\": no connection";
+        let e = pages("top", (), cmd!(do_broken_top)).await.err().unwrap();
+        assert_eq!(e.to_string(), msg);
+        let e = combined("top", (), cmd!(do_broken_top))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), msg);
+    }
+}
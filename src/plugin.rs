@@ -0,0 +1,277 @@
+/*!
+ * External sub-commands, provided by executables on the user's `PATH`.  At a
+ * level where plugins are enabled, a command that is not registered is looked
+ * for as an executable named for the level and the command; e.g., "frob" at
+ * the "trial thing" level would be provided by "trial-thing-frob".
+ */
+
+use super::Output;
+use anyhow::{bail, Result};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/**
+ * The unsuccessful exit of an external sub-command.  The plugin is expected
+ * to have reported the problem itself, so when this error is reported by
+ * `hiercmd::run()` nothing is printed and the exit status of the plugin
+ * becomes the exit status of the process.
+ */
+#[derive(Debug)]
+pub struct PluginExit {
+    path: PathBuf,
+    code: Option<i32>,
+}
+
+impl std::fmt::Display for PluginExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(code) = self.code {
+            write!(f, "{} exited with status {}", self.path.display(), code)
+        } else {
+            write!(f, "{} was terminated by a signal", self.path.display())
+        }
+    }
+}
+
+impl std::error::Error for PluginExit {}
+
+impl PluginExit {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+     * The exit status of the plugin, or `None` if it did not exit normally;
+     * e.g., if it was terminated by a signal.
+     */
+    pub fn code(&self) -> Option<i32> {
+        self.code
+    }
+}
+
+fn exe_name(prefix: &str, cmd: &str) -> String {
+    format!("{}-{}{}", prefix, cmd, std::env::consts::EXE_SUFFIX)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|md| md.is_file() && md.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/**
+ * Locate the executable for the external command `cmd` in the directories of
+ * `search`, which has the form of the `PATH` environment variable.  The
+ * `prefix` is the names of each level joined by hyphens; e.g.,
+ * "trial-thing".
+ */
+pub(crate) fn find(
+    search: Option<&OsStr>,
+    prefix: &str,
+    cmd: &str,
+) -> Option<PathBuf> {
+    if cmd.is_empty() || cmd.starts_with('-') {
+        return None;
+    }
+    let name = exe_name(prefix, cmd);
+    std::env::split_paths(search?)
+        .map(|dir| dir.join(&name))
+        .find(|path| is_executable(path))
+}
+
+/**
+ * List the names of the external commands available in the directories of
+ * `search`, in order and without duplicates.  Otherwise identical to
+ * `find()`.
+ */
+pub(crate) fn list(search: Option<&OsStr>, prefix: &str) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let search = if let Some(search) = search {
+        search
+    } else {
+        return out;
+    };
+
+    let start = format!("{}-", prefix);
+    for dir in std::env::split_paths(search) {
+        let mut found = Vec::new();
+        for ent in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let name = ent.file_name();
+            let cmd = name
+                .to_str()
+                .and_then(|n| n.strip_prefix(&start))
+                .and_then(|n| n.strip_suffix(std::env::consts::EXE_SUFFIX));
+            if let Some(cmd) = cmd {
+                if !cmd.is_empty()
+                    && !out.iter().any(|c| c == cmd)
+                    && is_executable(&ent.path())
+                {
+                    found.push(cmd.to_string());
+                }
+            }
+        }
+        found.sort();
+        found.dedup();
+        out.extend(found);
+    }
+    out
+}
+
+/**
+ * Run an external command with the provided arguments.  If the output for
+ * the level is being captured, the output of the command is captured along
+ * with it; otherwise, the command shares our stdio.  This blocks the calling
+ * thread until the command exits, so it is run through `blocking::unblock()`
+ * when dispatching the command.
+ */
+pub(crate) fn run(
+    path: &Path,
+    args: &[String],
+    mut stdout: Output,
+    mut stderr: Output,
+) -> Result<()> {
+    let mut cmd = Command::new(path);
+    cmd.args(args);
+
+    let status = if stdout.is_captured() || stderr.is_captured() {
+        let res = match cmd.stdin(Stdio::null()).output() {
+            Ok(res) => res,
+            Err(e) => bail!("could not run {}: {}", path.display(), e),
+        };
+        stdout.write_all(&res.stdout)?;
+        stderr.write_all(&res.stderr)?;
+        res.status
+    } else {
+        match cmd.status() {
+            Ok(status) => status,
+            Err(e) => bail!("could not run {}: {}", path.display(), e),
+        }
+    };
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(PluginExit {
+            path: path.to_path_buf(),
+            code: status.code(),
+        }
+        .into())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{find, list, run, PluginExit};
+    use crate::{Level, Output};
+    use std::ffi::OsString;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    fn tmpdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "hiercmd-plugin-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn script(dir: &Path, name: &str, body: &str, mode: u32) {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+            .unwrap();
+    }
+
+    #[test]
+    fn plugin_find_and_run() {
+        let a = tmpdir("a");
+        let b = tmpdir("b");
+        script(&a, "trial-frob", "echo frob \"$@\"; echo oops >&2", 0o755);
+        script(&a, "trial-thing-frob", "exit 3", 0o755);
+        script(&a, "trial-plain", "exit 0", 0o644);
+        script(&b, "trial-frob", "exit 0", 0o755);
+        script(&b, "trial-zap", "exit 0", 0o755);
+        let search = std::env::join_paths([&a, &b]).unwrap();
+        let search = Some(search.as_os_str());
+
+        assert_eq!(find(search, "trial", "frob"), Some(a.join("trial-frob")));
+        assert_eq!(find(search, "trial", "plain"), None);
+        assert_eq!(find(search, "trial", "-x"), None);
+        assert_eq!(find(None, "trial", "frob"), None);
+        assert_eq!(list(search, "trial"), ["frob", "thing-frob", "zap"]);
+        assert_eq!(list(search, "trial-thing"), ["frob"]);
+        assert!(list(Some(&OsString::new()), "trial").is_empty());
+
+        let stdout = Output::capture();
+        let stderr = Output::capture();
+        run(
+            &a.join("trial-frob"),
+            &["x".to_string(), "y".to_string()],
+            stdout.clone(),
+            stderr.clone(),
+        )
+        .unwrap();
+        assert_eq!(stdout.captured(), "frob x y\n");
+        assert_eq!(stderr.captured(), "oops\n");
+
+        let e = run(
+            &a.join("trial-thing-frob"),
+            &[],
+            Output::capture(),
+            Output::capture(),
+        )
+        .unwrap_err();
+        let pe = e.downcast_ref::<PluginExit>().unwrap();
+        assert_eq!(pe.code(), Some(3));
+
+        std::fs::remove_dir_all(&a).ok();
+        std::fs::remove_dir_all(&b).ok();
+    }
+
+    #[test]
+    fn plugin_search_env() {
+        let a = tmpdir("env");
+        script(&a, "trial-frob", "exit 0", 0o755);
+
+        /*
+         * The search path comes from the same environment as option values.
+         */
+        let mklevel = |path: &Path| {
+            let mut l = Level::new_sub(
+                vec!["trial".to_string()],
+                (),
+                Some(Vec::new()),
+                Default::default(),
+            );
+            l.shared.env = Some(Arc::new(
+                std::iter::once(("PATH".to_string(), OsString::from(path)))
+                    .collect(),
+            ));
+            l.plugin_commands(true);
+            l
+        };
+
+        let l = mklevel(&a);
+        assert_eq!(l.find_plugin("frob"), Some(a.join("trial-frob")));
+        assert!(l.gen_usage().contains("frob"));
+
+        let l = mklevel(&a.join("missing"));
+        assert_eq!(l.find_plugin("frob"), None);
+        assert!(!l.gen_usage().contains("frob"));
+
+        std::fs::remove_dir_all(&a).ok();
+    }
+}
@@ -0,0 +1,233 @@
+/*!
+ * Declarative positional arguments for terminal levels.  Each argument has a
+ * name, a description, and an arity, and may have a validator; the arguments
+ * provided by the user are assigned to them in order during argument parsing.
+ */
+
+use anyhow::Result;
+use std::sync::Arc;
+
+/**
+ * How many values a positional argument accepts.  Required arguments must
+ * come before optional ones, and only the last argument may be variadic.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    Required,
+    Optional,
+    /**
+     * Any number of values between `min` and `max` (inclusive), or with no
+     * upper bound if `max` is `None`.
+     */
+    Variadic {
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+type Validator = Arc<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
+/**
+ * A positional argument registered with `Level::positional()`.
+ */
+#[derive(Clone)]
+pub struct Positional {
+    pub(crate) name: String,
+    pub(crate) desc: String,
+    pub(crate) arity: Arity,
+    validator: Option<Validator>,
+}
+
+impl Positional {
+    pub(crate) fn new(name: &str, desc: &str, arity: Arity) -> Positional {
+        Positional {
+            name: name.to_string(),
+            desc: desc.to_string(),
+            arity,
+            validator: None,
+        }
+    }
+
+    /**
+     * Check each value provided for this argument with `func`.  If the
+     * function returns an error, it is reported as a usage error for the
+     * level.
+     */
+    pub fn validator<F>(&mut self, func: F) -> &mut Positional
+    where
+        F: Fn(&str) -> Result<()> + Send + Sync + 'static,
+    {
+        self.validator = Some(Arc::new(func));
+        self
+    }
+
+    fn min(&self) -> usize {
+        match self.arity {
+            Arity::Required => 1,
+            Arity::Optional => 0,
+            Arity::Variadic { min, .. } => min,
+        }
+    }
+
+    /**
+     * How this argument appears in the usage synopsis; e.g., "NAME",
+     * "[NAME]", or "NAME...".
+     */
+    pub(crate) fn synopsis(&self) -> String {
+        match self.arity {
+            Arity::Required => self.name.to_string(),
+            Arity::Optional => format!("[{}]", self.name),
+            Arity::Variadic { min: 0, .. } => format!("[{}...]", self.name),
+            Arity::Variadic { .. } => format!("{}...", self.name),
+        }
+    }
+}
+
+/**
+ * Assign the positional arguments provided by the user to each declared
+ * argument, in order.  Optional arguments only take a value if there will
+ * still be enough left for the variadic argument, if any.  On failure, the
+ * message for a usage error is returned.
+ */
+pub(crate) fn assign(
+    decls: &[Positional],
+    free: &[String],
+) -> std::result::Result<Vec<(String, Vec<String>)>, String> {
+    let mut out = Vec::new();
+    let mut missing = Vec::new();
+    let mut rest = free;
+
+    for (i, p) in decls.iter().enumerate() {
+        let later: usize = decls[i + 1..].iter().map(Positional::min).sum();
+        let take = match p.arity {
+            Arity::Required => rest.len().min(1),
+            Arity::Optional => usize::from(rest.len() > later),
+            Arity::Variadic { max, .. } => {
+                max.map(|max| rest.len().min(max)).unwrap_or(rest.len())
+            }
+        };
+        if take < p.min() {
+            if p.min() > 1 {
+                missing.push(format!("{} (at least {})", p.name, p.min()));
+            } else {
+                missing.push(p.name.to_string());
+            }
+        }
+
+        let (vals, r) = rest.split_at(take);
+        rest = r;
+        for v in vals.iter() {
+            if let Some(validator) = &p.validator {
+                if let Err(e) = validator(v) {
+                    return Err(format!(
+                        "invalid value \"{}\" for argument {}: {}",
+                        v, p.name, e
+                    ));
+                }
+            }
+        }
+        out.push((p.name.to_string(), vals.to_vec()));
+    }
+
+    if !missing.is_empty() {
+        return Err(format!("missing arguments: {}", missing.join(", ")));
+    }
+    if !rest.is_empty() {
+        return Err(format!("unexpected arguments: {}", rest.join(" ")));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Arity;
+    use crate::tests::{level, level_with, usage_message};
+    use crate::Level;
+
+    #[test]
+    fn positional_arguments() {
+        fn register(l: &mut Level<()>) {
+            l.positional("SRC", "source file", Arity::Required)
+                .unwrap()
+                .validator(|s| {
+                    if s.starts_with('-') {
+                        anyhow::bail!("must not start with \"-\"");
+                    }
+                    Ok(())
+                });
+            l.positional("MODE", "file mode", Arity::Optional).unwrap();
+            l.positional(
+                "DST",
+                "destination files",
+                Arity::Variadic {
+                    min: 1,
+                    max: Some(2),
+                },
+            )
+            .unwrap();
+        }
+
+        let mut l = level_with(&["a", "b"], register);
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.positional("SRC"), Some("a"));
+        assert_eq!(a.positional("MODE"), None);
+        assert_eq!(a.positionals("DST"), ["b"]);
+
+        let mut l = level_with(&["a", "b", "c", "d"], register);
+        let a = l.parse().unwrap().unwrap();
+        assert_eq!(a.positional("MODE"), Some("b"));
+        assert_eq!(a.positionals("DST"), ["c", "d"]);
+        assert!(a.positionals("BOGUS").is_empty());
+
+        assert_eq!(usage_message(&[], register), "missing arguments: SRC, DST");
+        assert_eq!(
+            usage_message(&["a", "b", "c", "d", "e"], register),
+            "unexpected arguments: e"
+        );
+        assert_eq!(
+            usage_message(&["--", "-a", "b"], register),
+            "invalid value \"-a\" for argument SRC: must not start with \"-\""
+        );
+
+        let usage = level_with(&[], register).gen_usage();
+        assert!(usage.starts_with("Usage: trial sub SRC [MODE] DST...\n"));
+        assert!(
+            usage.contains("Arguments:\n    SRC                 source file\n")
+        );
+    }
+
+    #[test]
+    fn positional_order() {
+        let declare = |arities: &[Arity]| {
+            let mut l = level(&[]);
+            for (i, arity) in arities.iter().enumerate() {
+                l.positional(&format!("A{}", i), "", *arity)?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let err = |arities: &[Arity]| declare(arities).unwrap_err().to_string();
+        let any = Arity::Variadic { min: 0, max: None };
+
+        declare(&[Arity::Required, Arity::Optional, any]).unwrap();
+        assert_eq!(
+            err(&[any, Arity::Optional]),
+            "positional argument A1 follows variadic argument A0"
+        );
+        assert_eq!(
+            err(&[any, any]),
+            "positional argument A1 follows variadic argument A0"
+        );
+        assert_eq!(
+            err(&[Arity::Optional, Arity::Required]),
+            "required positional argument A1 follows optional argument A0"
+        );
+        assert_eq!(
+            err(&[Arity::Variadic {
+                min: 3,
+                max: Some(2)
+            }]),
+            "variadic argument A0 has a minimum (3) greater than its \
+            maximum (2)"
+        );
+    }
+}
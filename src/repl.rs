@@ -0,0 +1,445 @@
+/*!
+ * An interactive shell over the command tree.  Each line the user enters is
+ * split into words as a shell would, and dispatched through the top-level
+ * handler as if those words had been passed to the program.  The same
+ * context object is passed to the command on each line, and handed back to
+ * the shell when the command is done with it, so that anything it holds
+ * (e.g., an API client) is reused across commands, and any changes made to it
+ * through `context_mut()` are seen by later commands.  Errors are reported as
+ * they would be by `hiercmd::run()`, but do not end the session.
+ *
+ * The shell is generally started from a sub-command; e.g.,
+ *
+ * ```ignore
+ * async fn do_shell(mut l: Level<Context>) -> Result<()> {
+ *     no_args!(l);
+ *     hiercmd::repl::run(l, cmd!(do_trial)).await
+ * }
+ * ```
+ *
+ * The handler must parse its arguments before starting the shell, so that it
+ * can be described like any other level for completion.
+ */
+
+use super::describe::{self, LevelInfo};
+use super::{
+    blocking, caller, complete, report, words, Caller, Level, LevelContext,
+    Shared, HELP_COMMAND, NO_CONTEXT,
+};
+use anyhow::{bail, Result};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::any::{Any, TypeId};
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const EXIT_COMMANDS: &[&str] = &["exit", "quit"];
+
+/**
+ * The place to which the context object is handed back once the command on
+ * each line is done with it.  Only the shell's own type of context object is
+ * kept, as the context objects made for commands registered with
+ * `Level::cmd_with()` are not needed once those commands are done.
+ */
+#[derive(Clone)]
+pub(crate) struct Home {
+    kind: TypeId,
+    slot: Arc<Mutex<Option<Box<dyn Any + Send>>>>,
+}
+
+impl Home {
+    fn new<C: Any + Send>() -> Home {
+        Home {
+            kind: TypeId::of::<C>(),
+            slot: Default::default(),
+        }
+    }
+
+    pub fn stash<C: Any + Send>(&self, context: C) {
+        if TypeId::of::<C>() == self.kind {
+            *self.slot.lock().unwrap() = Some(Box::new(context));
+        }
+    }
+
+    fn is_occupied(&self) -> bool {
+        self.slot.lock().unwrap().is_some()
+    }
+
+    fn take<C: Any + Send>(&self) -> Option<C> {
+        self.slot
+            .lock()
+            .unwrap()
+            .take()
+            .and_then(|c| c.downcast::<C>().ok())
+            .map(|c| *c)
+    }
+}
+
+/**
+ * Completion for the line editor, driven by a description of the whole
+ * command tree taken when the shell starts.
+ */
+struct TreeHelper {
+    levels: Vec<LevelInfo>,
+}
+
+impl Completer for TreeHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_line(&self.levels, &line[..pos]))
+    }
+}
+
+impl Hinter for TreeHelper {
+    type Hint = String;
+}
+
+impl Highlighter for TreeHelper {}
+
+impl Validator for TreeHelper {}
+
+impl Helper for TreeHelper {}
+
+/**
+ * Run an interactive shell over the command tree below the top-level handler
+ * `func`, until the user enters "exit" or "quit", or the end of input.  The
+ * `level` is that of the command that starts the shell, and provides the
+ * context object and the destination for output.
+ */
+pub async fn run<C, F, Fut>(level: Level<C>, func: F) -> Result<()>
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    run_common(level, caller(func), None).await
+}
+
+/**
+ * Run an interactive shell as per `run()`, loading the line history from the
+ * file at `history` if it exists, and saving it there at the end of the
+ * session.
+ */
+pub async fn run_with_history<C, F, Fut>(
+    level: Level<C>,
+    func: F,
+    history: &Path,
+) -> Result<()>
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    run_common(level, caller(func), Some(history)).await
+}
+
+async fn run_common<C: LevelContext>(
+    mut level: Level<C>,
+    func: Caller<C>,
+    history: Option<&Path>,
+) -> Result<()> {
+    if level.shared.interactive.is_some() {
+        bail!("already in an interactive shell");
+    }
+
+    let name = level.names[0].to_string();
+    let private = match level.private.take() {
+        Some(c) => c,
+        None => bail!(NO_CONTEXT),
+    };
+    let home = Home::new::<C>();
+    let shared = Shared {
+        stdout: level.stdout(),
+        stderr: level.stderr(),
+        interactive: Some(home.clone()),
+        ..Default::default()
+    };
+
+    let (levels, private) =
+        describe::walk_context(&name, private, func.clone(), false).await?;
+    home.stash(private);
+    let mut editor = Editor::<TreeHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(TreeHelper { levels }));
+    if let Some(path) = history {
+        /*
+         * There is no history the first time the shell is used.
+         */
+        editor.load_history(path).ok();
+    }
+
+    let prompt = format!("{}> ", name);
+    let res = loop {
+        /*
+         * Reading a line blocks until the user has entered it, so it is done
+         * away from the executor, and the editor is handed back with the
+         * line.
+         */
+        let p = prompt.clone();
+        let (e, res) = blocking::unblock(move || {
+            let res = editor.readline(&p);
+            (editor, res)
+        })
+        .await;
+        editor = e;
+
+        let line = match res {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break Ok(()),
+            Err(e) => break Err(e.into()),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        match dispatch::<C>(&name, &home, &func, &shared, &line).await {
+            Ok(true) => (),
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    if let Some(path) = history {
+        editor.save_history(path)?;
+    }
+    res
+}
+
+/**
+ * Run the command on one line of input with the context object from `home`,
+ * and report any error.  Returns false if the user has asked to leave the
+ * shell.  If the command did not hand the context object back, there is none
+ * for the next line, so that is an error.
+ */
+async fn dispatch<C: LevelContext>(
+    name: &str,
+    home: &Home,
+    func: &Caller<C>,
+    shared: &Shared,
+    line: &str,
+) -> Result<bool> {
+    let mut stderr = shared.stderr.clone();
+    let args = match words::split(line) {
+        Ok(args) => args,
+        Err(e) => {
+            writeln!(stderr, "ERROR: {}", e).ok();
+            return Ok(true);
+        }
+    };
+    match args.first().map(String::as_str) {
+        None => return Ok(true),
+        Some(w) if args.len() == 1 && EXIT_COMMANDS.contains(&w) => {
+            return Ok(false)
+        }
+        Some(_) => (),
+    }
+
+    /*
+     * The context object is always at home between lines, as we check that
+     * each command hands it back.
+     */
+    let private = home.take::<C>().unwrap();
+    let l = Level::new_sub(
+        vec![name.to_string()],
+        private,
+        Some(args),
+        shared.clone(),
+    );
+    let res = func(l).await;
+    report(
+        res,
+        &shared.take_position(vec![name.to_string()]),
+        &mut stderr,
+    );
+
+    if !home.is_occupied() {
+        bail!("the command did not hand back the context object");
+    }
+    Ok(true)
+}
+
+/**
+ * Determine the completion candidates for the word under the cursor at the
+ * end of `line`, and where that word starts.  The earlier words are followed
+ * through the tree to find the level to which the word belongs.
+ */
+fn complete_line(levels: &[LevelInfo], line: &str) -> (usize, Vec<String>) {
+    let start = line
+        .rfind(char::is_whitespace)
+        .map(|i| i + line[i..].chars().next().unwrap().len_utf8())
+        .unwrap_or(0);
+    let partial = &line[start..];
+    let words = if let Ok(words) = words::split(&line[..start]) {
+        words
+    } else {
+        return (start, Vec::new());
+    };
+    let mut info = if let Some(top) = levels.first() {
+        top
+    } else {
+        return (start, Vec::new());
+    };
+
+    /*
+     * Follow the command names through the tree, skipping options and their
+     * values.  Once a word that is not a command has been seen, the rest of
+     * the words are positional arguments for the level.
+     */
+    let mut args = Vec::new();
+    let mut free: Option<Vec<String>> = None;
+    let mut value = false;
+    for w in words.iter() {
+        if let Some(free) = free.as_mut() {
+            free.push(w.to_string());
+            continue;
+        }
+        args.push(w.to_string());
+        if value {
+            value = false;
+            continue;
+        }
+        if w.starts_with('-') && w != "-" {
+            value = info.options.iter().any(|o| o.hasarg && o.named_by(w));
+            continue;
+        }
+        if w == HELP_COMMAND
+            && !info.commands.is_empty()
+            && !info.commands.iter().any(|c| c.name == HELP_COMMAND)
+        {
+            continue;
+        }
+
+        let sub = info
+            .commands
+            .iter()
+            .find(|c| {
+                c.name == *w
+                    || c.aliases.contains(w)
+                    || c.hidden_aliases.contains(w)
+            })
+            .and_then(|c| {
+                let mut names = info.names.clone();
+                names.push(c.name.to_string());
+                levels.iter().find(|l| l.names == names)
+            });
+        if let Some(sub) = sub {
+            info = sub;
+            args.clear();
+        } else {
+            free = Some(vec![w.to_string()]);
+        }
+    }
+
+    /*
+     * If the last word is an option that takes a value, the level appears
+     * as it would have if option parsing had failed for want of the value.
+     */
+    let info = LevelInfo {
+        args,
+        free: if value {
+            None
+        } else {
+            Some(free.unwrap_or_default())
+        },
+        ..info.clone()
+    };
+    (start, complete::candidates(&info, partial))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete_line, dispatch, Home};
+    use crate::caller;
+    use crate::describe::walk;
+    use crate::prelude::*;
+    use crate::{Output, Shared};
+    use anyhow::Result;
+
+    #[derive(Default)]
+    struct Counter(usize);
+
+    async fn do_list(mut l: Level<Counter>) -> Result<()> {
+        l.add_column("name", 8, true);
+        l.add_column("size", 8, true);
+        no_args!(l);
+        let c = l.context_mut();
+        c.0 += 1;
+        let n = c.0;
+        writeln!(l.stdout(), "listed {}", n)?;
+        Ok(())
+    }
+
+    async fn do_thing(mut l: Level<Counter>) -> Result<()> {
+        l.cmda("list", "ls", "list things", cmd!(do_list))?;
+        sel!(l).run().await
+    }
+
+    async fn do_top(mut l: Level<Counter>) -> Result<()> {
+        l.cmd("thing", "manage things", cmd!(do_thing))?;
+        l.optflag("x", "extend", "extend");
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn repl_dispatch() {
+        let home = Home::new::<Counter>();
+        let shared = Shared {
+            stdout: Output::capture(),
+            stderr: Output::capture(),
+            interactive: Some(home.clone()),
+            ..Default::default()
+        };
+        home.stash(Counter::default());
+        let top = caller(do_top);
+        let lines = ["thing list", "", "  thing 'ls'", "thing lsit", "x\"y"];
+        for line in lines.iter().chain(&["help", "thing ls"]) {
+            assert!(dispatch("top", &home, &top, &shared, line).await.unwrap());
+        }
+        assert!(!dispatch("top", &home, &top, &shared, "exit").await.unwrap());
+
+        /*
+         * The same context object is used for every line, including after
+         * a failed command and the built-in help command.
+         */
+        assert!(shared.stdout.captured().starts_with("listed 1\nlisted 2\n"));
+        assert!(shared.stdout.captured().ends_with("listed 3\n"));
+        assert_eq!(home.take::<Counter>().unwrap().0, 3);
+        let stderr = shared.stderr.captured();
+        assert!(stderr.contains("Usage: top thing COMMAND"));
+        assert!(stderr.contains(
+            "ERROR: command \"lsit\" not understood; did you mean \"list\"?\n"
+        ));
+        assert!(stderr.ends_with("ERROR: unterminated double quote\n"));
+    }
+
+    #[tokio::test]
+    async fn repl_complete() {
+        let levels = walk("top", Counter::default(), caller(do_top), false)
+            .await
+            .unwrap();
+        let complete = |line: &str| {
+            let (start, c) = complete_line(&levels, line);
+            (start, c.join(" "))
+        };
+
+        assert_eq!(complete(""), (0, "thing help".to_string()));
+        assert_eq!(complete("-x th"), (3, "thing".to_string()));
+        assert_eq!(complete("thing l"), (6, "list ls".to_string()));
+        assert_eq!(complete("help thing "), (11, "list ls help".to_string()));
+        assert_eq!(complete("thing ls -o n"), (12, "name".to_string()));
+        assert_eq!(complete("thing ls --h"), (9, "--help".to_string()));
+        assert_eq!(complete("bogus "), (6, String::new()));
+    }
+}
@@ -0,0 +1,99 @@
+/*!
+ * Suggestions for likely alternatives when the user provides a command,
+ * option, or column name that we do not recognise.
+ */
+
+/**
+ * Compute the optimal string alignment distance between two strings; i.e.,
+ * the number of insertions, deletions, substitutions, and transpositions of
+ * adjacent characters required to turn one into the other.
+ */
+fn distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/**
+ * Find the candidate closest to what the user provided, as long as it is
+ * close enough that it was plausibly a typo.
+ */
+pub(crate) fn suggest<I, S>(want: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let limit = (want.chars().count() / 3).max(1);
+
+    let mut best: Option<(usize, String)> = None;
+    for c in candidates {
+        let c = c.as_ref();
+        let dist = distance(want, c);
+        if dist == 0 || dist > limit {
+            continue;
+        }
+        if best.as_ref().map(|(bd, _)| dist < *bd).unwrap_or(true) {
+            best = Some((dist, c.to_string()));
+        }
+    }
+
+    best.map(|(_, c)| c)
+}
+
+/**
+ * Produce a suffix for an error message that suggests an alternative, if we
+ * can find one; e.g., `; did you mean "list"?`.
+ */
+pub(crate) fn did_you_mean<I, S>(want: &str, candidates: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    suggest(want, candidates)
+        .map(|s| format!("; did you mean \"{}\"?", s))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{distance, suggest};
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("list", "list"), 0);
+        assert_eq!(distance("lsit", "list"), 1);
+        assert_eq!(distance("lst", "list"), 1);
+        assert_eq!(distance("info", "list"), 4);
+        assert_eq!(distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["info", "thing", "nothing", "list"];
+        assert_eq!(suggest("lsit", names).as_deref(), Some("list"));
+        assert_eq!(suggest("thnig", names).as_deref(), Some("thing"));
+        assert_eq!(suggest("zzz", names), None);
+        assert_eq!(suggest("list", names), None);
+    }
+}
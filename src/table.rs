@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use super::suggest::suggest;
 use anyhow::{bail, Result};
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -13,10 +14,25 @@ enum Value {
 }
 
 #[derive(Clone)]
-struct Column {
-    name: String,
-    width: usize,
-    default: bool,
+pub(crate) struct Column {
+    pub(crate) name: String,
+    pub(crate) width: usize,
+    pub(crate) default: bool,
+}
+
+/**
+ * Describe a set of invalid column names, suggesting a valid column for any
+ * that look like a typo.
+ */
+fn invalid_columns(missing: &[String], outputs: &[Column]) -> String {
+    let names = missing
+        .iter()
+        .map(|n| match suggest(n, outputs.iter().map(|o| &o.name)) {
+            Some(s) => format!("{} (did you mean \"{}\"?)", n, s),
+            None => n.to_string(),
+        })
+        .collect::<Vec<_>>();
+    format!("invalid column names: {}", names.join(", "))
 }
 
 #[derive(Default)]
@@ -92,7 +108,7 @@ impl Table {
                 .collect::<Vec<_>>();
             missing.sort();
             if !missing.is_empty() {
-                bail!("invalid column names: {}", missing.join(", "));
+                bail!("{}", invalid_columns(&missing, &self.outputs));
             }
 
             filter
@@ -198,7 +214,7 @@ impl Table {
         let mut out = self.output_unsorted_header_common(&filter)?;
 
         for row in self.data.iter() {
-            out += &self.output_unsorted_common(&row, &filter)?;
+            out += &self.output_unsorted_common(row, &filter)?;
         }
 
         Ok(out)
@@ -475,6 +491,10 @@ impl TableBuilder {
         self
     }
 
+    pub(crate) fn columns(&self) -> &[Column] {
+        &self.outputs
+    }
+
     pub fn column_names(&self) -> Vec<String> {
         let mut names = self
             .outputs
@@ -503,6 +523,19 @@ impl TableBuilder {
         }
     }
 
+    /**
+     * If any of the column names in the output filter are not valid, produce
+     * an error message describing them.
+     */
+    pub fn missing_column_error(&self) -> Option<String> {
+        let mcn = self.missing_column_names();
+        if mcn.is_empty() {
+            None
+        } else {
+            Some(invalid_columns(&mcn, &self.outputs))
+        }
+    }
+
     /**
      * Construct the final Table object, to which rows may be appended for
      * eventual display.
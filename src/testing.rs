@@ -0,0 +1,171 @@
+/*!
+ * Facilities for exercising a whole command tree from a test, without
+ * starting a new process.  The top-level handler is invoked with a provided
+ * set of arguments, and anything the handlers write to the `stdout()` and
+ * `stderr()` of each level is captured along with the exit status.
+ */
+
+use super::{caller, run_common, Level, LevelContext, Output, Shared};
+use anyhow::Result;
+use std::future::Future;
+
+/**
+ * The outcome of a command invocation through `invoke()`.
+ */
+pub struct Invocation {
+    status: u8,
+    stdout: String,
+    stderr: String,
+}
+
+impl Invocation {
+    /**
+     * The exit status the process would have had; i.e., the status returned
+     * by `hiercmd::run()`.
+     */
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+
+    pub fn stdout(&self) -> &str {
+        &self.stdout
+    }
+
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+}
+
+/**
+ * Invoke the top-level handler `func` with a default context object.  The
+ * first element of `args` is the command name, as it would be in the argument
+ * vector for a process; e.g.,
+ *
+ * ```ignore
+ * let res = invoke(cmd!(do_trial), &["trial", "thing", "list"]).await;
+ * assert_eq!(res.stdout(), "NAME\nThing One\nThing Two\n");
+ * ```
+ */
+pub async fn invoke<C, F, Fut>(func: F, args: &[&str]) -> Invocation
+where
+    C: LevelContext + Default,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    invoke_with(C::default(), func, args).await
+}
+
+/**
+ * Invoke the top-level handler `func` with the provided context object.
+ * Otherwise identical to `invoke()`.
+ */
+pub async fn invoke_with<C, F, Fut>(
+    private: C,
+    func: F,
+    args: &[&str],
+) -> Invocation
+where
+    C: LevelContext,
+    F: Fn(Level<C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let (name, args) = args.split_first().expect("command name required");
+
+    let shared = Shared {
+        stdout: Output::capture(),
+        stderr: Output::capture(),
+        ..Default::default()
+    };
+    let l = Level::new_sub(
+        vec![name.to_string()],
+        private,
+        Some(args.iter().map(|s| s.to_string()).collect()),
+        shared.clone(),
+    );
+
+    let status = run_common(l, caller(func)).await;
+
+    Invocation {
+        status,
+        stdout: shared.stdout.captured(),
+        stderr: shared.stderr.captured(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::invoke;
+    use crate::prelude::*;
+    use anyhow::Result;
+
+    async fn do_list(mut l: Level<()>) -> Result<()> {
+        l.add_column("name", 8, true);
+        l.add_column("size", 6, false);
+        no_args!(l);
+        writeln!(l.stdout(), "listed")?;
+        Ok(())
+    }
+
+    async fn do_fail(mut l: Level<()>) -> Result<()> {
+        no_args!(l);
+        anyhow::bail!("it failed");
+    }
+
+    async fn do_top(mut l: Level<()>) -> Result<()> {
+        l.cmda("list", "ls", "list things", cmd!(do_list))?;
+        l.cmd("fail", "fail to do things", cmd!(do_fail))?;
+        sel!(l).run().await
+    }
+
+    #[tokio::test]
+    async fn invoke_success() {
+        let res = invoke(cmd!(do_top), &["top", "ls", "-o", "size"]).await;
+        assert!(res.success());
+        assert_eq!(res.stdout(), "listed\n");
+        assert_eq!(res.stderr(), "");
+    }
+
+    #[tokio::test]
+    async fn invoke_help() {
+        let res = invoke(cmd!(do_top), &["top", "list", "--help"]).await;
+        assert!(res.success());
+        assert!(res.stdout().starts_with("Usage: top list [OPTS]\n"));
+        assert!(res.stdout().contains("Columns:\n    name"));
+    }
+
+    #[tokio::test]
+    async fn invoke_help_command() {
+        let res = invoke(cmd!(do_top), &["top", "help", "list"]).await;
+        assert!(res.success());
+        assert!(res.stdout().starts_with("Usage: top list [OPTS]\n"));
+
+        let res = invoke(cmd!(do_top), &["top", "help"]).await;
+        assert!(res.success());
+        assert_eq!(
+            res.stdout(),
+            "Commands:\n    \
+            list (ls)           list things\n    \
+            fail                fail to do things\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_usage_error() {
+        let res = invoke(cmd!(do_top), &["top", "list", "-o", "x"]).await;
+        assert_eq!(res.status(), 2);
+        assert_eq!(res.stdout(), "");
+        assert!(res.stderr().starts_with("Usage: top list [OPTS]\n"));
+        assert!(res.stderr().ends_with("ERROR: invalid column names: x\n"));
+    }
+
+    #[tokio::test]
+    async fn invoke_failure() {
+        let res = invoke(cmd!(do_top), &["top", "fail"]).await;
+        assert_eq!(res.status(), 1);
+        assert_eq!(res.stderr(), "ERROR: top fail: it failed\n");
+    }
+}
@@ -0,0 +1,196 @@
+/*!
+ * Parsing of human-friendly quantities, such as durations like "1h30m" and
+ * sizes like "4G", for use with the typed option accessors on `Arguments`.
+ */
+
+use std::time::Duration;
+
+/**
+ * Split a string into a sequence of numbers, each followed by a (possibly
+ * empty) unit suffix; e.g., "1h30m" becomes [("1", "h"), ("30", "m")].
+ */
+fn terms(s: &str) -> Result<Vec<(&str, &str)>, String> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let n = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        if n == 0 {
+            return Err("expected a number".into());
+        }
+        let (num, r) = rest.split_at(n);
+        let u = r.find(|c: char| c.is_ascii_digit()).unwrap_or(r.len());
+        let (unit, r) = r.split_at(u);
+        out.push((num, unit));
+        rest = r;
+    }
+    if out.is_empty() {
+        return Err("empty value".into());
+    }
+    Ok(out)
+}
+
+fn number(num: &str) -> Result<f64, String> {
+    num.parse::<f64>()
+        .map_err(|_| format!("invalid number \"{}\"", num))
+}
+
+/**
+ * Parse a duration made up of one or more numbers with a unit; e.g., "10m",
+ * "1h30m", or "1.5s".  The units are "ms", "s", "m", "h", and "d".  A number
+ * without a unit is a count of seconds.
+ */
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let terms = terms(s)?;
+    let mut total = 0.0;
+    for (num, unit) in terms.iter() {
+        let scale = match *unit {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            other => {
+                return Err(format!(
+                    "unknown unit \"{}\" (expected ms, s, m, h, or d)",
+                    other
+                ))
+            }
+        };
+        total += number(num)? * scale;
+    }
+    Duration::try_from_secs_f64(total).map_err(|e| e.to_string())
+}
+
+/**
+ * Parse a size in bytes, with an optional binary unit suffix; e.g., "512",
+ * "4G", or "1.5MiB".  The units are "K", "M", "G", "T", and "P", optionally
+ * followed by "B" or "iB", and are not case-sensitive.
+ */
+pub(crate) fn parse_bytes(s: &str) -> Result<u64, String> {
+    let terms = terms(s)?;
+    let (num, unit) = match terms.as_slice() {
+        [term] => *term,
+        _ => return Err("expected a single number".into()),
+    };
+
+    let unit = unit.to_ascii_uppercase();
+    let prefix = unit
+        .strip_suffix("IB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let shift = match prefix {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        "P" => 50,
+        _ => {
+            return Err(format!(
+                "unknown unit \"{}\" (expected K, M, G, T, or P)",
+                unit
+            ))
+        }
+    };
+
+    if !num.contains('.') {
+        let n = num.parse::<u64>().map_err(|e| e.to_string())?;
+        return n
+            .checked_mul(1 << shift)
+            .ok_or_else(|| "value is too large".to_string());
+    }
+
+    let n = number(num)? * (1u64 << shift) as f64;
+    if n >= u64::MAX as f64 {
+        return Err("value is too large".into());
+    }
+    Ok(n as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bytes, parse_duration};
+    use crate::tests::level_with;
+    use crate::{Level, UsageError};
+    use std::time::Duration;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10y").is_err());
+        assert!(parse_duration("1..5s").is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(parse_bytes("512"), Ok(512));
+        assert_eq!(parse_bytes("4G"), Ok(4 << 30));
+        assert_eq!(parse_bytes("4gb"), Ok(4 << 30));
+        assert_eq!(parse_bytes("1.5MiB"), Ok(3 << 19));
+        assert!(parse_bytes("4X").is_err());
+        assert!(parse_bytes("1G2M").is_err());
+        assert!(parse_bytes("20000000P").is_err());
+    }
+
+    #[test]
+    fn typed_options() {
+        fn register(l: &mut Level<()>) {
+            l.optopt("c", "count", "number of things", "COUNT");
+            l.optopt("", "timeout", "how long to wait", "DURATION");
+            l.optopt("", "size", "size of things", "BYTES");
+            l.optmulti("t", "", "tags", "TAG,...");
+        }
+        let parse = |args: &[&str]| {
+            level_with(args, register).parse().unwrap().unwrap()
+        };
+
+        let a = parse(&[
+            "-c",
+            "12",
+            "--timeout",
+            "1h30m",
+            "--size",
+            "4G",
+            "-t",
+            "a,b",
+            "-t",
+            "c",
+        ]);
+        assert_eq!(a.opt_u64("count").unwrap(), Some(12));
+        assert_eq!(a.opt_parse::<i8>("c").unwrap(), Some(12));
+        assert_eq!(
+            a.opt_duration("timeout").unwrap(),
+            Some(Duration::from_secs(5400))
+        );
+        assert_eq!(a.opt_bytes("size").unwrap(), Some(4 << 30));
+        assert_eq!(a.opt_list("t"), ["a", "b", "c"]);
+
+        let a = parse(&[]);
+        assert_eq!(a.opt_u64("count").unwrap(), None);
+        assert!(a.opt_list("t").is_empty());
+
+        let a = parse(&["-c", "many", "--timeout", "soon"]);
+        let e = a.opt_u64("count").expect_err("parse should fail");
+        let ue = e.downcast_ref::<UsageError>().expect("usage error");
+        assert_eq!(
+            ue.message(),
+            "invalid value \"many\" for option -c (--count): \
+            invalid digit found in string"
+        );
+        assert_eq!(ue.names(), &["trial", "sub"]);
+        assert!(ue.usage().starts_with("Usage: trial sub [OPTS]"));
+        let e = a.opt_duration("timeout").expect_err("parse should fail");
+        assert_eq!(
+            e.to_string(),
+            "invalid value \"soon\" for option --timeout: expected a number"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+/*!
+ * Splitting a line of text into words in the manner of a POSIX shell, for
+ * command aliases and other places where the user provides a whole command
+ * line as a single string.  Only quoting and escaping are interpreted; there
+ * is no expansion of variables, globs, or anything else.
+ */
+
+use anyhow::{bail, Result};
+
+/**
+ * Split `line` into words at unquoted whitespace.  Within single quotes every
+ * character is literal.  Within double quotes, a backslash escapes only a
+ * double quote, a backslash, a dollar sign, or a backtick.  Elsewhere, a
+ * backslash escapes any character.
+ */
+pub(crate) fn split(line: &str) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(w) = word.take() {
+                    out.push(w);
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => bail!("unterminated double quote"),
+                        },
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => bail!("trailing backslash"),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if let Some(w) = word.take() {
+        out.push(w);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    #[test]
+    fn words_split() {
+        for (line, words) in [
+            ("", vec![]),
+            ("  thing   list ", vec!["thing", "list"]),
+            ("-o name,number", vec!["-o", "name,number"]),
+            ("say 'hello  world'", vec!["say", "hello  world"]),
+            ("a\"b c\"d", vec!["ab cd"]),
+            ("\"\" ''", vec!["", ""]),
+            ("\"\\\"x\\n\"", vec!["\"x\\n"]),
+            ("one\\ word", vec!["one word"]),
+        ] {
+            assert_eq!(split(line).unwrap(), words, "{:?}", line);
+        }
+
+        for (line, msg) in [
+            ("'open", "unterminated single quote"),
+            ("\"open", "unterminated double quote"),
+            ("end\\", "trailing backslash"),
+        ] {
+            let e = split(line).unwrap_err();
+            assert_eq!(e.to_string(), msg, "{:?}", line);
+        }
+    }
+}